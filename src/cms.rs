@@ -0,0 +1,151 @@
+use crate::der::*;
+use crate::keys;
+use crate::x509;
+use tinyvec::Array;
+
+// signedData (1.2.840.113549.1.7.2)
+const OID_SIGNED_DATA: [u8; 9] = [0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x07, 0x02];
+// contentType (1.2.840.113549.1.9.3)
+const OID_CONTENT_TYPE: [u8; 9] = [0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x03];
+// messageDigest (1.2.840.113549.1.9.4)
+const OID_MESSAGE_DIGEST: [u8; 9] = [0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x04];
+// id-sha256 (2.16.840.1.101.3.4.2.1)
+const OID_SHA256: [u8; 9] = [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+
+// pivSigner-DN (2.16.840.1.101.3.6.5)
+const OID_PIV_SIGNER_DN: [u8; 8] = [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x06, 0x05];
+
+// id-PIV-CHUIDSecurityObject (2.16.840.1.101.3.6.1)
+pub const OID_PIV_CHUID_SECURITY_OBJECT: [u8; 8] = [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x06, 0x01];
+// id-icao-ldsSecurityObject (2.23.136.1.1.1)
+const OID_LDS_SECURITY_OBJECT: [u8; 6] = [0x67, 0x81, 0x08, 0x01, 0x01, 0x01];
+
+fn write_algorithm<A: Array<Item = u8>>(w: &mut DerWriter<A>, oid: &[u8]) {
+    let alg = w.open(TAG_SEQUENCE);
+    w.write(TAG_OID, oid);
+    w.close(alg);
+}
+
+/// CMS SignedData (RFC 5652) with detached content, signed by the content
+/// signer key.
+///
+/// `digest` is the SHA-256 digest of the content and `content_type` its
+/// eContentType. The content signer certificate is embedded so the signature
+/// can be checked against it.
+/// The signer name is included as the pivSigner-DN signed attribute, as
+/// required for the CHUID signature.
+pub fn write_signed_data<A: Array<Item = u8>>(
+    w: &mut DerWriter<A>,
+    content_type: &[u8],
+    digest: &[u8; 32],
+) {
+    write_signed_data_with(w, content_type, true, |_| *digest);
+}
+
+/// ICAO LDS Security Object (Doc 9303 part 10), listing the SHA-256 digests
+/// of data groups, in a CMS SignedData signed by the content signer key
+pub fn write_lds_security_object<A: Array<Item = u8>>(
+    w: &mut DerWriter<A>,
+    data_groups: &[(u8, [u8; 32])],
+) {
+    write_signed_data_with(w, &OID_LDS_SECURITY_OBJECT, false, |w| {
+        let explicit = w.open(0xA0);
+        let econtent = w.open(TAG_OCTET_STRING);
+
+        let content_start = w.len();
+        let lds = w.open(TAG_SEQUENCE);
+        w.integer(&[0]);
+        write_algorithm(w, &OID_SHA256);
+        let hashes = w.open(TAG_SEQUENCE);
+        for (number, hash) in data_groups {
+            let data_group = w.open(TAG_SEQUENCE);
+            w.integer(&[*number]);
+            w.write(TAG_OCTET_STRING, hash);
+            w.close(data_group);
+        }
+        w.close(hashes);
+        w.close(lds);
+        let digest = keys::sha256(w.slice_from(content_start));
+
+        w.close(econtent);
+        w.close(explicit);
+        digest
+    });
+}
+
+/// Write a SignedData, `encapsulate` writes the optional eContent and
+/// returns the digest of the content
+fn write_signed_data_with<A: Array<Item = u8>>(
+    w: &mut DerWriter<A>,
+    content_type: &[u8],
+    piv_signer_dn: bool,
+    encapsulate: impl FnOnce(&mut DerWriter<A>) -> [u8; 32],
+) {
+    let pvkey = keys::content_signer_private_key();
+
+    let content_info = w.open(TAG_SEQUENCE);
+    w.write(TAG_OID, &OID_SIGNED_DATA);
+    let explicit = w.open(0xA0);
+    let signed_data = w.open(TAG_SEQUENCE);
+    w.integer(&[3]);
+
+    let digest_algorithms = w.open(TAG_SET);
+    write_algorithm(w, &OID_SHA256);
+    w.close(digest_algorithms);
+
+    let encap_content_info = w.open(TAG_SEQUENCE);
+    w.write(TAG_OID, content_type);
+    let digest = encapsulate(w);
+    w.close(encap_content_info);
+
+    let certificates = w.open(0xA0);
+    x509::write_content_signer_certificate(w, &pvkey);
+    w.close(certificates);
+
+    let signer_infos = w.open(TAG_SET);
+    let signer_info = w.open(TAG_SEQUENCE);
+    w.integer(&[1]);
+    let sid = w.open(TAG_SEQUENCE);
+    x509::write_content_signer_name(w);
+    w.integer(&x509::content_signer_serial());
+    w.close(sid);
+    write_algorithm(w, &OID_SHA256);
+
+    // Signed attributes are signed with a SET OF tag, then stored with an
+    // implicit [0] tag
+    let attrs_start = w.len();
+    let attrs = w.open(TAG_SET);
+    let attr = w.open(TAG_SEQUENCE);
+    w.write(TAG_OID, &OID_CONTENT_TYPE);
+    let values = w.open(TAG_SET);
+    w.write(TAG_OID, content_type);
+    w.close(values);
+    w.close(attr);
+    let attr = w.open(TAG_SEQUENCE);
+    w.write(TAG_OID, &OID_MESSAGE_DIGEST);
+    let values = w.open(TAG_SET);
+    w.write(TAG_OCTET_STRING, &digest);
+    w.close(values);
+    w.close(attr);
+    // Being the longest, it comes last in DER order
+    if piv_signer_dn {
+        let attr = w.open(TAG_SEQUENCE);
+        w.write(TAG_OID, &OID_PIV_SIGNER_DN);
+        let values = w.open(TAG_SET);
+        x509::write_content_signer_name(w);
+        w.close(values);
+        w.close(attr);
+    }
+    w.close(attrs);
+    let signature = keys::sign_sha256(&pvkey, w.slice_from(attrs_start));
+    w.set_tag(attrs_start, 0xA0);
+
+    write_algorithm(w, &x509::OID_ECDSA_WITH_SHA256);
+    w.write(TAG_OCTET_STRING, &signature);
+    w.close(signer_info);
+    w.close(signer_infos);
+
+    w.close(signed_data);
+    w.close(explicit);
+    w.close(content_info);
+}
@@ -1,46 +1,109 @@
+use crate::cms;
 use crate::data_response::*;
+use crate::keys;
+use crate::settings::{self, PinPolicy};
+use crate::slots;
 use crate::status::*;
+use crate::storage::{self, StoredObject, Writer};
+use crate::tlv;
 use crate::utils::*;
+use crate::x509;
 
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum DataObjectIdentifier {
     DiscoveryObject,
     CardHolderUniqueIdentifier,
     CardCapabilitiesContainer,
+    // Key reference of the primary slot
+    SlotCertificate(u8),
     RetiredCertificate(u8),
     KeyHistory,
+    SecurityObject,
+    PrintedInformation,
+    FacialImage,
+    Fingerprints,
+    IrisImages,
+    ContentSignerCertificate,
+    AttestationCertificate,
+    // Any other 5Fxxxx object, opaque to the card
+    Generic([u8; 3]),
     UnknownObjectIdentifier,
 }
 
-// Key History Object
-// (https://nvlpubs.nist.gov/nistpubs/specialpublications/nist.sp.800-73-4.pdf, Table 19)
-const N_SLOTS_SUPPORTED: u8 = 1;
-const KEY_HISTORY_OBJECT: [u8; 8] = [0xC1, 0x01, N_SLOTS_SUPPORTED, 0xC2, 0x01, 0x00, 0xFE, 0x00];
+/// Read access rule of a data object
+/// (https://nvlpubs.nist.gov/nistpubs/specialpublications/nist.sp.800-73-4.pdf, Table 2)
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum AccessRule {
+    Always,
+    Pin,
+}
 
 // Discovery Object
 // (https://nvlpubs.nist.gov/nistpubs/specialpublications/nist.sp.800-73-4.pdf, Table 18)
-const DISCOVERY_RESPONSE: [u8; 20] = [
-    0x7E, 0x12, 0x4F, 0x0B, 0xA0, 0x00, 0x00, 0x03, 0x08, 0x00, 0x00, 0x10, 0x00, 0x01, 0x00, 0x5F,
-    0x2F, 0x02, 0x40, 0x00,
+//
+// Full PIV AID, followed by the PIN Usage Policy computed from settings
+const DISCOVERY_AID: [u8; 11] = [
+    0xA0, 0x00, 0x00, 0x03, 0x08, 0x00, 0x00, 0x10, 0x00, 0x01, 0x00,
 ];
+// First byte of PIN Usage Policy
+const PIN_POLICY_PIV_PIN: u8 = 0x40;
+const PIN_POLICY_GLOBAL_PIN: u8 = 0x20;
+// Second byte of PIN Usage Policy, when the Global PIN satisfies ACRs
+const PIN_POLICY_PIV_PIN_PRIMARY: u8 = 0x10;
+const PIN_POLICY_GLOBAL_PIN_PRIMARY: u8 = 0x20;
 
 // CHUID Object
 // (https://nvlpubs.nist.gov/nistpubs/specialpublications/nist.sp.800-73-4.pdf, Table 9)
 //
-// FASC-N (with agency 9999, generated using poc/fasc-n_encode.py)
+// FASC-N (encoded at runtime from settings, agency 9999 by default)
 // UUID (v5 from serial number added at runtime)
-// Expiry date: January 2050
-// Empty signature
+// Expiry date (from settings, January 2050 by default)
+// Issuer asymmetric signature (CMS SignedData computed at runtime)
 // Empty Error detection code
-const CHUID_OBJECT_LEN: u8 = 59;
-const CHUID_OBJECT_PREFIX: [u8; 29] = [
-    0x30, 0x19, 0xD4, 0xE7, 0x39, 0xDA, 0x73, 0x9C, 0xED, 0x39, 0xCE, 0x73, 0x9D, 0x83, 0x68, 0x58,
-    0x21, 0x08, 0x42, 0x10, 0x84, 0x21, 0xCC, 0x33, 0x9E, 0x23, 0xFF, 0x34, 0x10,
+
+// Security Object
+// (https://nvlpubs.nist.gov/nistpubs/specialpublications/nist.sp.800-73-4.pdf, Table 12)
+//
+// Container ID and identifier of the objects served by the card, but the
+// certificates which are already signed. Those present on the card are
+// covered by the Security Object, numbered in this order.
+const SECURITY_OBJECT_DATA_GROUPS: [(u16, DataObjectIdentifier); 6] = [
+    (0x3000, DataObjectIdentifier::CardHolderUniqueIdentifier),
+    (0xDB00, DataObjectIdentifier::CardCapabilitiesContainer),
+    (0x3001, DataObjectIdentifier::PrintedInformation),
+    (0x6010, DataObjectIdentifier::Fingerprints),
+    (0x6030, DataObjectIdentifier::FacialImage),
+    (0x6060, DataObjectIdentifier::KeyHistory),
 ];
-const CHUID_OBJECT_SUFFIX: [u8; 14] = [
-    0x35, 0x08, 0x32, 0x30, 0x35, 0x30, 0x30, 0x31, 0x30, 0x31, 0x3e, 0x00, 0xfe, 0x00,
+
+// Printed Information
+// (https://nvlpubs.nist.gov/nistpubs/specialpublications/nist.sp.800-73-4.pdf, Table 14)
+//
+// Tag and maximum length of the fields: name, employee affiliation,
+// expiration date, agency card serial number, issuer identification and
+// organization affiliation (2 lines). Other fields, such as the management
+// key protected by the PIN stored by ykman, are kept as written.
+const PRINTED_INFORMATION_FIELDS: [(u16, usize); 7] = [
+    (0x01, 125),
+    (0x02, 20),
+    (0x04, 9),
+    (0x05, 10),
+    (0x06, 15),
+    (0x07, 20),
+    (0x08, 20),
 ];
 
+// Content signer certificate (vendor object)
+//
+// Self-signed certificate of the key signing the CHUID, to be used as trust
+// anchor by relying parties.
+const CONTENT_SIGNER_CERTIFICATE_TAG: [u8; 3] = [0x5F, 0xFF, 0x20];
+
+// Attestation certificate (Yubico object)
+//
+// Self-signed certificate of the key signing attestations, see INS F9.
+const ATTESTATION_CERTIFICATE_TAG: [u8; 3] = [0x5F, 0xFF, 0x01];
+
 // Card Capabilities Container
 // (https://nvlpubs.nist.gov/nistpubs/specialpublications/nist.sp.800-73-4.pdf, Table 8)
 //
@@ -66,7 +129,245 @@ const CCC_OBJECT_SUFFIX: [u8; 28] = [
     0xf7, 0x00, 0xfa, 0x00, 0xfb, 0x00, 0xfc, 0x00, 0xfd, 0x00, 0xfe, 0x00,
 ];
 
-fn set_retired_certificate_data(response_buffer: &mut DataResponseBuffer, _i: u8) {
+fn set_chuid_data(response_buffer: &mut DataResponseBuffer) -> Result<(), StatusWord> {
+    let mut w = response_buffer.der_writer();
+    let object = w.open(0x53);
+
+    // The signature covers every CHUID element preceding it
+    let content_start = w.len();
+    w.write(0x30, &settings::get().fascn.encode());
+    w.write(0x34, &card_uuid());
+    w.write(0x35, &settings::get().chuid_expiry);
+    let digest = keys::sha256(w.slice_from(content_start));
+
+    let signature = w.open(0x3E);
+    cms::write_signed_data(&mut w, &cms::OID_PIV_CHUID_SECURITY_OBJECT, &digest);
+    w.close(signature);
+    w.raw(&[0xFE, 0x00]);
+
+    w.close(object);
+    w.finish()
+}
+
+/// Mapping of data groups to container IDs followed by the signed SHA-256
+/// digests of the data objects values
+fn set_security_object_data(response_buffer: &mut DataResponseBuffer) -> Result<(), StatusWord> {
+    // Only objects present on the card are covered
+    let mut hashes = [(0u8, [0u8; 32]); SECURITY_OBJECT_DATA_GROUPS.len()];
+    let mut container_ids = [0u16; SECURITY_OBJECT_DATA_GROUPS.len()];
+    let mut count = 0;
+    for (container_id, object) in SECURITY_OBJECT_DATA_GROUPS {
+        let digest = match object.security_object_digest(response_buffer) {
+            Ok(digest) => digest,
+            Err(StatusWord::FileNotFound) => continue,
+            Err(s) => return Err(s),
+        };
+        hashes[count] = (count as u8 + 1, digest);
+        container_ids[count] = container_id;
+        count += 1;
+    }
+
+    let mut w = response_buffer.der_writer();
+    let object = w.open(0x53);
+    let mapping = w.open(0xBA);
+    for ((number, _), container_id) in hashes[..count].iter().zip(container_ids) {
+        w.raw(&[*number]);
+        w.raw(&container_id.to_be_bytes());
+    }
+    w.close(mapping);
+    let security_object = w.open(0xBB);
+    cms::write_lds_security_object(&mut w, &hashes[..count]);
+    w.close(security_object);
+    w.raw(&[0xFE, 0x00]);
+    w.close(object);
+    w.finish()
+}
+
+/// Check Printed Information fields
+fn check_printed_information(value: &[u8]) -> Result<(), StatusWord> {
+    if value.len() > storage::PRINTED_INFORMATION_MAX_LEN {
+        return Err(StatusWord::WrongLength);
+    }
+
+    let mut data = value;
+    while !data.is_empty() {
+        let (field, rest) = tlv::parse(data).ok_or(StatusWord::WrongData)?;
+        // Error detection code ends the buffer
+        if field.tag == 0xFE && !rest.is_empty() {
+            return Err(StatusWord::WrongData);
+        }
+        if let Some((_, max_len)) = PRINTED_INFORMATION_FIELDS
+            .iter()
+            .find(|(tag, _)| *tag == field.tag)
+        {
+            if field.value.len() > *max_len {
+                return Err(StatusWord::WrongData);
+            }
+        }
+        data = rest;
+    }
+    Ok(())
+}
+
+/// Certificate of a primary slot, as stored by the host or generated
+fn set_slot_certificate_data(
+    response_buffer: &mut DataResponseBuffer,
+    key_ref: u8,
+) -> Result<(), StatusWord> {
+    let index = slots::primary_index(key_ref).ok_or(StatusWord::FileNotFound)?;
+    if storage::get(StoredObject::Certificate(index)).is_some() {
+        return set_stored_data(response_buffer, StoredObject::Certificate(index));
+    }
+    set_generated_certificate_data(response_buffer, key_ref)
+}
+
+/// Certificate of a slot key, generated when the host did not store one
+fn set_generated_certificate_data(
+    response_buffer: &mut DataResponseBuffer,
+    key_ref: u8,
+) -> Result<(), StatusWord> {
+    if !slots::is_populated(key_ref) {
+        return Err(StatusWord::FileNotFound);
+    }
+
+    let mut w = response_buffer.der_writer();
+    let object = w.open(0x53);
+    let certificate = w.open(0x70);
+    x509::write_slot_certificate(&mut w, key_ref);
+    w.close(certificate);
+    w.raw(&[0x71, 0x01, 0x00, 0xFE, 0x00]);
+    w.close(object);
+    w.finish()
+}
+
+/// Object stored in NVM, streamed after its '53' tag header
+fn set_stored_data(
+    response_buffer: &mut DataResponseBuffer,
+    object: StoredObject,
+) -> Result<(), StatusWord> {
+    let content = storage::get(object).ok_or(StatusWord::FileNotFound)?;
+
+    let mut w = response_buffer.der_writer();
+    w.header(0x53, content.len());
+    w.finish()?;
+    response_buffer.stream(content);
+    Ok(())
+}
+
+/// Certificate of the key signing the CHUID, wrapped like the PIV
+/// certificate objects
+fn set_content_signer_certificate_data(
+    response_buffer: &mut DataResponseBuffer,
+) -> Result<(), StatusWord> {
+    let pvkey = keys::content_signer_private_key();
+
+    let mut w = response_buffer.der_writer();
+    let object = w.open(0x53);
+    let certificate = w.open(0x70);
+    x509::write_content_signer_certificate(&mut w, &pvkey);
+    w.close(certificate);
+    w.raw(&[0x71, 0x01, 0x00, 0xFE, 0x00]);
+    w.close(object);
+    w.finish()
+}
+
+/// Certificate of the attestation key, wrapped like the PIV certificate
+/// objects
+fn set_attestation_certificate_data(
+    response_buffer: &mut DataResponseBuffer,
+) -> Result<(), StatusWord> {
+    let mut w = response_buffer.der_writer();
+    let object = w.open(0x53);
+    let certificate = w.open(0x70);
+    x509::write_attestation_ca_certificate(&mut w);
+    w.close(certificate);
+    w.raw(&[0x71, 0x01, 0x00, 0xFE, 0x00]);
+    w.close(object);
+    w.finish()
+}
+
+/// Discovery Object, advertising the PIN policy from settings
+///
+/// Neither the On-Card Comparison nor the Virtual Contact Interface (and
+/// hence pairing code) is supported, their bits are never set.
+fn set_discovery_data(response_buffer: &mut DataResponseBuffer) -> Result<(), StatusWord> {
+    let pin_usage_policy = match settings::get().pin_policy {
+        PinPolicy::PivOnly => [PIN_POLICY_PIV_PIN, 0x00],
+        PinPolicy::GlobalAllowed => [
+            PIN_POLICY_PIV_PIN | PIN_POLICY_GLOBAL_PIN,
+            PIN_POLICY_PIV_PIN_PRIMARY,
+        ],
+        PinPolicy::GlobalPrimary => [
+            PIN_POLICY_PIV_PIN | PIN_POLICY_GLOBAL_PIN,
+            PIN_POLICY_GLOBAL_PIN_PRIMARY,
+        ],
+    };
+
+    let mut w = response_buffer.der_writer();
+    let object = w.open(0x7E);
+    w.write(0x4F, &DISCOVERY_AID);
+    w.write(0x5F2F, &pin_usage_policy);
+    w.close(object);
+    w.finish()
+}
+
+/// Object stored in the blob store, streamed after its '53' tag header
+fn set_blob_data(
+    response_buffer: &mut DataResponseBuffer,
+    tag: &[u8; 3],
+) -> Result<(), StatusWord> {
+    let value = storage::get_blob(tag).ok_or(StatusWord::FileNotFound)?;
+
+    let mut w = response_buffer.der_writer();
+    w.header(0x53, value.len());
+    w.finish()?;
+    response_buffer.stream(value);
+    Ok(())
+}
+
+/// Key History Object
+/// (https://nvlpubs.nist.gov/nistpubs/specialpublications/nist.sp.800-73-4.pdf, Table 19)
+///
+/// Number of retired keys with an on-card and an off-card certificate, and
+/// URL of the off-card certificates.
+fn set_key_history_data(response_buffer: &mut DataResponseBuffer) -> Result<(), StatusWord> {
+    let settings = settings::get();
+
+    let mut w = response_buffer.der_writer();
+    let object = w.open(0x53);
+    w.write(0xC1, &[slots::retired_on_card()]);
+    w.write(0xC2, &[settings.off_card_certificates]);
+    if settings.off_card_url_len != 0 {
+        w.write(0xF3, &settings.off_card_url[..settings.off_card_url_len]);
+    }
+    w.raw(&[0xFE, 0x00]);
+    w.close(object);
+    w.finish()
+}
+
+/// Certificate of a retired slot key, as stored by the host, built-in for the
+/// initial key of the first retired slot, or generated
+fn set_retired_certificate_data(
+    response_buffer: &mut DataResponseBuffer,
+    index: u8,
+) -> Result<(), StatusWord> {
+    let key_ref = slots::retired_key_ref(index);
+    if !slots::is_populated(key_ref) {
+        return Err(StatusWord::FileNotFound);
+    }
+
+    let object = StoredObject::RetiredCertificate(index as usize);
+    if storage::get(object).is_some() {
+        set_stored_data(response_buffer, object)
+    } else if slots::has_factory_key(key_ref) {
+        set_builtin_retired_certificate_data(response_buffer);
+        Ok(())
+    } else {
+        set_generated_certificate_data(response_buffer, key_ref)
+    }
+}
+
+fn set_builtin_retired_certificate_data(response_buffer: &mut DataResponseBuffer) {
     // Compute data buffer: todo
     let hardcoded = [
         0x53, 0x82, 0x01, 0xbf, 0x70, 0x82, 0x01, 0xb6, 0x30, 0x82, 0x01, 0xb2, 0x30, 0x82, 0x01,
@@ -108,18 +409,42 @@ impl DataObjectIdentifier {
     pub fn from(val: &[u8]) -> Self {
         match val.len() {
             3 => {
-                if val[..2] != [0x5F, 0xC1] {
+                if val == CONTENT_SIGNER_CERTIFICATE_TAG {
+                    Self::ContentSignerCertificate
+                } else if val == ATTESTATION_CERTIFICATE_TAG {
+                    Self::AttestationCertificate
+                } else if val[0] != 0x5F {
                     Self::UnknownObjectIdentifier
+                } else if val[1] != 0xC1 {
+                    Self::Generic([val[0], val[1], val[2]])
+                } else if val[2] == 0x01 {
+                    Self::SlotCertificate(0x9E)
                 } else if val[2] == 0x02 {
                     Self::CardHolderUniqueIdentifier
+                } else if val[2] == 0x03 {
+                    Self::Fingerprints
+                } else if val[2] == 0x05 {
+                    Self::SlotCertificate(0x9A)
+                } else if val[2] == 0x06 {
+                    Self::SecurityObject
                 } else if val[2] == 0x07 {
                     Self::CardCapabilitiesContainer
+                } else if val[2] == 0x08 {
+                    Self::FacialImage
+                } else if val[2] == 0x09 {
+                    Self::PrintedInformation
+                } else if val[2] == 0x0A {
+                    Self::SlotCertificate(0x9C)
+                } else if val[2] == 0x0B {
+                    Self::SlotCertificate(0x9D)
                 } else if val[2] == 0x0C {
                     Self::KeyHistory
                 } else if 0x0D <= val[2] && val[2] <= 0x20 {
                     Self::RetiredCertificate(val[2] - 0x0D)
+                } else if val[2] == 0x21 {
+                    Self::IrisImages
                 } else {
-                    Self::UnknownObjectIdentifier
+                    Self::Generic([val[0], val[1], val[2]])
                 }
             }
             1 => {
@@ -133,40 +458,160 @@ impl DataObjectIdentifier {
         }
     }
 
+    /// Condition to fulfill before reading the object
+    pub fn read_access(&self) -> AccessRule {
+        match self {
+            Self::PrintedInformation
+            | Self::FacialImage
+            | Self::Fingerprints
+            | Self::IrisImages => AccessRule::Pin,
+            _ => AccessRule::Always,
+        }
+    }
+
     pub fn handle(&self, response_buffer: &mut DataResponseBuffer) -> Result<(), StatusWord> {
         if *self == Self::DiscoveryObject {
-            response_buffer.set(&DISCOVERY_RESPONSE);
-            return Ok(());
+            return set_discovery_data(response_buffer);
         }
 
         response_buffer.set(&[0x53]);
 
         match self {
-            Self::CardHolderUniqueIdentifier => {
-                response_buffer.extend(&[CHUID_OBJECT_LEN]);
-                response_buffer.extend(&CHUID_OBJECT_PREFIX);
-                response_buffer.extend(&device_uuid());
-                response_buffer.extend(&CHUID_OBJECT_SUFFIX);
-                Ok(())
-            }
+            Self::CardHolderUniqueIdentifier => set_chuid_data(response_buffer),
             Self::CardCapabilitiesContainer => {
                 response_buffer.extend(&[CCC_OBJECT_LEN]);
                 response_buffer.extend(&CCC_OBJECT_PREFIX);
-                response_buffer.extend(&device_uuid()[..14]);
+                response_buffer.extend(&card_uuid()[..14]);
                 response_buffer.extend(&CCC_OBJECT_SUFFIX);
                 Ok(())
             }
 
-            Self::KeyHistory => {
-                response_buffer.extend(&[KEY_HISTORY_OBJECT.len() as u8]);
-                response_buffer.extend(&KEY_HISTORY_OBJECT);
-                Ok(())
+            Self::KeyHistory => set_key_history_data(response_buffer),
+            Self::SlotCertificate(key_ref) => set_slot_certificate_data(response_buffer, *key_ref),
+            Self::FacialImage => set_stored_data(response_buffer, StoredObject::FacialImage),
+            Self::Fingerprints => set_stored_data(response_buffer, StoredObject::Fingerprints),
+            Self::RetiredCertificate(i) => set_retired_certificate_data(response_buffer, *i),
+            Self::SecurityObject => set_security_object_data(response_buffer),
+            Self::PrintedInformation => {
+                let content = storage::get(StoredObject::PrintedInformation)
+                    .ok_or(StatusWord::FileNotFound)?;
+                let mut w = response_buffer.der_writer();
+                w.write(0x53, content);
+                w.finish()
+            }
+            Self::ContentSignerCertificate => set_content_signer_certificate_data(response_buffer),
+            Self::AttestationCertificate => set_attestation_certificate_data(response_buffer),
+            Self::Generic(tag) => set_blob_data(response_buffer, tag),
+            _ => Err(StatusWord::FileNotFound),
+        }
+    }
+
+    /// Object stored in NVM, along with the tag its content must start with
+    fn stored_object(&self) -> Option<(StoredObject, u8)> {
+        match self {
+            Self::SlotCertificate(key_ref) => {
+                let index = slots::primary_index(*key_ref)?;
+                Some((StoredObject::Certificate(index), 0x70))
             }
             Self::RetiredCertificate(i) => {
-                set_retired_certificate_data(response_buffer, *i);
-                Ok(())
+                Some((StoredObject::RetiredCertificate(*i as usize), 0x70))
             }
-            _ => Err(StatusWord::FileNotFound),
+            // CBEFF wrapped biometric data
+            Self::FacialImage => Some((StoredObject::FacialImage, 0xBC)),
+            Self::Fingerprints => Some((StoredObject::Fingerprints, 0xBC)),
+            _ => None,
+        }
+    }
+
+    /// Content of an object stored in NVM, None if the host did not write it
+    fn stored_content(&self) -> Option<&'static [u8]> {
+        storage::get(self.stored_object()?.0)
+    }
+
+    /// SHA-256 digest of the object value, as covered by the Security Object
+    ///
+    /// Objects stored in NVM are hashed in place, as they do not fit in the
+    /// response buffer.
+    fn security_object_digest(
+        &self,
+        response_buffer: &mut DataResponseBuffer,
+    ) -> Result<[u8; 32], StatusWord> {
+        if let Some((object, _)) = self.stored_object() {
+            let content = storage::get(object).ok_or(StatusWord::FileNotFound)?;
+            return Ok(keys::sha256(content));
+        }
+        self.handle(response_buffer)?;
+        let (value, _) = tlv::parse(response_buffer.content()).ok_or(StatusWord::WrongData)?;
+        Ok(keys::sha256(value.value))
+    }
+
+    /// Certificate object of a primary or retired slot
+    fn slot_certificate(key_ref: u8) -> Option<DataObjectIdentifier> {
+        if slots::primary_index(key_ref).is_some() {
+            Some(Self::SlotCertificate(key_ref))
+        } else if slots::RETIRED_SLOTS.contains(&key_ref) {
+            Some(Self::RetiredCertificate(
+                key_ref - slots::RETIRED_SLOTS.start(),
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Replace the content of a writable object, the `len` bytes long value
+    /// of the '53' tag, starting with `first_chunk`
+    ///
+    /// Objects stored in NVM may be received in several chunks, the writer
+    /// of the remaining content is then returned.
+    pub fn put(&self, len: usize, first_chunk: &[u8]) -> Result<Option<Writer>, StatusWord> {
+        if let Some((object, tag)) = self.stored_object() {
+            if len != 0 && first_chunk.first() != Some(&tag) {
+                return Err(StatusWord::WrongData);
+            }
+            let mut writer = Writer::start(object, len)?;
+            writer.append(first_chunk)?;
+            return Ok(Some(writer));
+        }
+        if let Self::Generic(tag) = self {
+            let mut writer = Writer::start_blob(tag, len)?;
+            writer.append(first_chunk)?;
+            return Ok(Some(writer));
+        }
+
+        if first_chunk.len() != len {
+            return Err(StatusWord::WrongLength);
+        }
+        match self {
+            Self::PrintedInformation => {
+                check_printed_information(first_chunk)?;
+                let mut writer = Writer::start(StoredObject::PrintedInformation, len)?;
+                writer.append(first_chunk)?;
+                Ok(None)
+            }
+            Self::UnknownObjectIdentifier => Err(StatusWord::FileNotFound),
+            _ => Err(StatusWord::FuncNotSupported),
         }
     }
 }
+
+/// Move the certificate written for the key of slot `from` to the certificate
+/// object of slot `to`, along with the key
+pub fn move_slot_certificate(from: u8, to: u8) -> Result<(), StatusWord> {
+    let source = DataObjectIdentifier::slot_certificate(from).ok_or(StatusWord::RefDataNotFound)?;
+    let destination =
+        DataObjectIdentifier::slot_certificate(to).ok_or(StatusWord::RefDataNotFound)?;
+
+    // The source is only erased once copied, a failed copy leaves it intact
+    let content = source.stored_content().unwrap_or(&[]);
+    destination.put(content.len(), content)?;
+    source.put(0, &[])?;
+    Ok(())
+}
+
+/// Erase the certificate written for the key of a slot, along with the key
+pub fn delete_slot_certificate(key_ref: u8) -> Result<(), StatusWord> {
+    let object =
+        DataObjectIdentifier::slot_certificate(key_ref).ok_or(StatusWord::RefDataNotFound)?;
+    object.put(0, &[])?;
+    Ok(())
+}
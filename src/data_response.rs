@@ -1,28 +1,42 @@
+use crate::der::DerWriter;
 use crate::status::*;
 use nanos_sdk::io;
 use tinyvec::ArrayVec;
 
 const APDU_MAX_CHUNK_SIZE: usize = 255;
-const DATA_RESP_BUFFER_SIZE: usize = 512;
+// Large enough for a CHUID embedding its content signer certificate
+const DATA_RESP_BUFFER_SIZE: usize = 1024;
 
 // When response is split across multiple APDU packets, remaining length to
 // read is sent to the host in the status word. The host ask the card to
 // continue the response with 0xC0 instruction.
+//
+// Large objects stored in NVM are not copied in the buffer: they are sent
+// from NVM after the buffer content, see `stream`.
 pub struct DataResponseBuffer {
     data: ArrayVec<[u8; DATA_RESP_BUFFER_SIZE]>,
+    // None rather than an empty slice, so that an empty buffer is all zeros
+    // and a static one is placed in .bss
+    tail: Option<&'static [u8]>,
     read_cnt: usize,
 }
 
 impl DataResponseBuffer {
-    pub fn new() -> DataResponseBuffer {
+    pub const fn new() -> DataResponseBuffer {
         Self {
-            data: ArrayVec::new(),
+            data: ArrayVec::from_array_empty([0; DATA_RESP_BUFFER_SIZE]),
+            tail: None,
             read_cnt: 0,
         }
     }
 
+    /// Data sent after the buffer content, see `stream`
+    fn tail(&self) -> &'static [u8] {
+        self.tail.unwrap_or(&[])
+    }
+
     fn remaining_length(&self) -> usize {
-        self.data.len() - self.read_cnt
+        self.data.len() + self.tail().len() - self.read_cnt
     }
 
     pub fn set(&mut self, data: &[u8]) {
@@ -34,7 +48,25 @@ impl DataResponseBuffer {
         self.data.extend_from_slice(&data[0..copied_length]);
 
         // Init read counter
+        self.tail = None;
+        self.read_cnt = 0;
+    }
+
+    /// Response data, as set by the last data object handler
+    pub fn content(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Build the response in place with a DER encoder
+    pub fn der_writer(&mut self) -> DerWriter<'_, [u8; DATA_RESP_BUFFER_SIZE]> {
+        self.tail = None;
         self.read_cnt = 0;
+        DerWriter::new(&mut self.data)
+    }
+
+    /// Send `tail` after the current content, without copying it
+    pub fn stream(&mut self, tail: &'static [u8]) {
+        self.tail = Some(tail);
     }
 
     pub fn extend(&mut self, data: &[u8]) {
@@ -47,12 +79,21 @@ impl DataResponseBuffer {
         APDU_MAX_CHUNK_SIZE.min(self.remaining_length())
     }
 
-    fn read_next_chunk(&mut self) -> &[u8] {
+    /// Append the next chunk to the response, taken from the buffer and then
+    /// from the tail
+    fn read_next_chunk(&mut self, comm: &mut io::Comm) {
         let read_length = self.get_next_chunk_size();
         let begin = self.read_cnt;
         let end = self.read_cnt + read_length;
         self.read_cnt += read_length;
-        &self.data[begin..end]
+
+        let split = self.data.len();
+        if begin < split {
+            comm.append(&self.data[begin..end.min(split)]);
+        }
+        if end > split {
+            comm.append(&self.tail()[begin.max(split) - split..end - split]);
+        }
     }
 
     pub fn send(&mut self, comm: &mut io::Comm) {
@@ -63,7 +104,7 @@ impl DataResponseBuffer {
         }
 
         // Read data
-        comm.append(self.read_next_chunk());
+        self.read_next_chunk(comm);
 
         // Reply status
         let next_size = self.get_next_chunk_size();
@@ -0,0 +1,147 @@
+use crate::status::*;
+use tinyvec::{Array, ArrayVec};
+
+// Universal DER tags
+pub const TAG_INTEGER: u16 = 0x02;
+pub const TAG_BIT_STRING: u16 = 0x03;
+pub const TAG_OID: u16 = 0x06;
+pub const TAG_OCTET_STRING: u16 = 0x04;
+pub const TAG_UTF8_STRING: u16 = 0x0C;
+pub const TAG_UTC_TIME: u16 = 0x17;
+pub const TAG_GENERALIZED_TIME: u16 = 0x18;
+pub const TAG_SEQUENCE: u16 = 0x30;
+pub const TAG_SET: u16 = 0x31;
+
+/// Encode a definite length, returning the buffer and the number of used bytes
+fn encode_length(len: usize) -> ([u8; 3], usize) {
+    if len < 0x80 {
+        ([len as u8, 0, 0], 1)
+    } else if len <= 0xFF {
+        ([0x81, len as u8, 0], 2)
+    } else {
+        ([0x82, (len >> 8) as u8, len as u8], 3)
+    }
+}
+
+/// DER (and BER-TLV) encoder writing into a fixed size buffer.
+///
+/// Constructed elements are started with `open`, which returns a mark to give
+/// back to `close` once the content is written: the length is only known at
+/// this point, so it is inserted before the content.
+/// Writes that would overflow the buffer are dropped and reported by
+/// `finish`.
+pub struct DerWriter<'a, A: Array<Item = u8>> {
+    data: &'a mut ArrayVec<A>,
+    overflow: bool,
+}
+
+impl<'a, A: Array<Item = u8>> DerWriter<'a, A> {
+    pub fn new(data: &'a mut ArrayVec<A>) -> Self {
+        data.clear();
+        Self {
+            data,
+            overflow: false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Bytes written since `start` (a previous value of `len`)
+    pub fn slice_from(&self, start: usize) -> &[u8] {
+        &self.data[start..]
+    }
+
+    /// Append already encoded bytes
+    pub fn raw(&mut self, bytes: &[u8]) {
+        if self.overflow || bytes.len() > self.data.capacity() - self.data.len() {
+            self.overflow = true;
+            return;
+        }
+        self.data.extend_from_slice(bytes);
+    }
+
+    fn tag(&mut self, tag: u16) {
+        if tag > 0xFF {
+            self.raw(&tag.to_be_bytes());
+        } else {
+            self.raw(&[tag as u8]);
+        }
+    }
+
+    /// Write a primitive element
+    pub fn write(&mut self, tag: u16, value: &[u8]) {
+        self.header(tag, value.len());
+        self.raw(value);
+    }
+
+    /// Write the tag and length of an element, its `len` bytes of content
+    /// being sent separately
+    pub fn header(&mut self, tag: u16, len: usize) {
+        let (len, len_size) = encode_length(len);
+        self.tag(tag);
+        self.raw(&len[..len_size]);
+    }
+
+    /// Write an unsigned big-endian integer
+    pub fn integer(&mut self, value: &[u8]) {
+        let skip = value.iter().take_while(|&&b| b == 0).count();
+        let value = &value[skip.min(value.len().saturating_sub(1))..];
+        if value.is_empty() || value[0] & 0x80 != 0 {
+            let (len, len_size) = encode_length(value.len() + 1);
+            self.tag(TAG_INTEGER);
+            self.raw(&len[..len_size]);
+            self.raw(&[0x00]);
+            self.raw(value);
+        } else {
+            self.write(TAG_INTEGER, value);
+        }
+    }
+
+    /// Write a bit string without unused bits
+    pub fn bit_string(&mut self, value: &[u8]) {
+        let (len, len_size) = encode_length(value.len() + 1);
+        self.tag(TAG_BIT_STRING);
+        self.raw(&len[..len_size]);
+        self.raw(&[0x00]);
+        self.raw(value);
+    }
+
+    /// Start a constructed element
+    pub fn open(&mut self, tag: u16) -> usize {
+        self.tag(tag);
+        self.data.len()
+    }
+
+    /// End a constructed element started with `open`
+    pub fn close(&mut self, mark: usize) {
+        if self.overflow {
+            return;
+        }
+        let (len, len_size) = encode_length(self.data.len() - mark);
+        if len_size > self.data.capacity() - self.data.len() {
+            self.overflow = true;
+            return;
+        }
+        for (i, b) in len[..len_size].iter().enumerate() {
+            self.data.insert(mark + i, *b);
+        }
+    }
+
+    /// Replace the one byte tag of the element starting at `start`
+    pub fn set_tag(&mut self, start: usize, tag: u8) {
+        if let Some(b) = self.data.get_mut(start) {
+            *b = tag;
+        }
+    }
+
+    pub fn finish(self) -> Result<(), StatusWord> {
+        if self.overflow {
+            self.data.clear();
+            Err(StatusWord::WrongLength)
+        } else {
+            Ok(())
+        }
+    }
+}
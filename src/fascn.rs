@@ -0,0 +1,182 @@
+// Federal Agency Smart Credential Number (FASC-N)
+// (https://www.hidglobal.com/sites/default/files/resource_files/6090-905_f.1_-_pivclass_fips-201_reader_operation_and_output_selections.pdf)
+//
+// Encoded as 40 characters of 5 bits: 4 data bits, least significant first,
+// followed by an odd parity bit.
+//
+// S AAAA F SSSS F CCCCCC F CS F ICI F PPPPPPPPPP OC OOOO POA E LRC
+
+pub const FASCN_LEN: usize = 25;
+
+// Number of digits of the FASC-N fields, see `Fascn::from_digits`
+pub const FASCN_DIGITS: usize = 32;
+
+const START_SENTINEL: u8 = 0xB;
+const FIELD_SEPARATOR: u8 = 0xD;
+const END_SENTINEL: u8 = 0xF;
+
+/// FASC-N fields, as decimal digits
+#[derive(Copy, Clone)]
+pub struct Fascn {
+    pub agency_code: [u8; 4],
+    pub system_code: [u8; 4],
+    pub credential_number: [u8; 6],
+    pub credential_series: u8,
+    pub individual_credential_issue: u8,
+    pub person_identifier: [u8; 10],
+    pub organizational_category: u8,
+    pub organizational_identifier: [u8; 4],
+    pub person_association: u8,
+}
+
+impl Fascn {
+    // Agency 9999, as previously generated by poc/fasc-n_encode.py
+    pub const DEFAULT: Fascn = Fascn {
+        agency_code: [9, 9, 9, 9],
+        system_code: [9, 9, 9, 9],
+        credential_number: [9, 9, 9, 9, 9, 9],
+        credential_series: 0,
+        individual_credential_issue: 1,
+        person_identifier: [0; 10],
+        organizational_category: 3,
+        organizational_identifier: [1, 3, 3, 7],
+        person_association: 2,
+    };
+
+    /// Parse the fields from ASCII digits, in encoding order
+    pub fn from_digits(digits: &[u8]) -> Option<Fascn> {
+        if digits.len() != FASCN_DIGITS || !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+
+        let mut values = [0u8; FASCN_DIGITS];
+        for (v, d) in values.iter_mut().zip(digits) {
+            *v = d - b'0';
+        }
+
+        let mut fascn = Fascn::DEFAULT;
+        fascn.agency_code.copy_from_slice(&values[0..4]);
+        fascn.system_code.copy_from_slice(&values[4..8]);
+        fascn.credential_number.copy_from_slice(&values[8..14]);
+        fascn.credential_series = values[14];
+        fascn.individual_credential_issue = values[15];
+        fascn.person_identifier.copy_from_slice(&values[16..26]);
+        fascn.organizational_category = values[26];
+        fascn
+            .organizational_identifier
+            .copy_from_slice(&values[27..31]);
+        fascn.person_association = values[31];
+        Some(fascn)
+    }
+
+    pub const fn encode(&self) -> [u8; FASCN_LEN] {
+        let mut enc = Encoder::new();
+        enc = enc.push(START_SENTINEL);
+        enc = enc.push_digits(&self.agency_code);
+        enc = enc.push(FIELD_SEPARATOR);
+        enc = enc.push_digits(&self.system_code);
+        enc = enc.push(FIELD_SEPARATOR);
+        enc = enc.push_digits(&self.credential_number);
+        enc = enc.push(FIELD_SEPARATOR);
+        enc = enc.push(self.credential_series);
+        enc = enc.push(FIELD_SEPARATOR);
+        enc = enc.push(self.individual_credential_issue);
+        enc = enc.push(FIELD_SEPARATOR);
+        enc = enc.push_digits(&self.person_identifier);
+        enc = enc.push(self.organizational_category);
+        enc = enc.push_digits(&self.organizational_identifier);
+        enc = enc.push(self.person_association);
+        enc = enc.push(END_SENTINEL);
+        let lrc = enc.lrc;
+        enc = enc.push(lrc);
+        enc.data
+    }
+}
+
+/// Bit writer of 5 bits characters, computing the LRC along the way
+#[derive(Copy, Clone)]
+struct Encoder {
+    data: [u8; FASCN_LEN],
+    bit: usize,
+    lrc: u8,
+}
+
+impl Encoder {
+    const fn new() -> Encoder {
+        Encoder {
+            data: [0; FASCN_LEN],
+            bit: 0,
+            lrc: 0,
+        }
+    }
+
+    const fn push_bit(mut self, b: u8) -> Encoder {
+        if b != 0 {
+            self.data[self.bit / 8] |= 0x80 >> (self.bit % 8);
+        }
+        self.bit += 1;
+        self
+    }
+
+    const fn push(mut self, value: u8) -> Encoder {
+        let mut i = 0;
+        while i < 4 {
+            self = self.push_bit((value >> i) & 1);
+            i += 1;
+        }
+        // Odd parity
+        self = self.push_bit(((value & 0xF).count_ones() as u8 + 1) & 1);
+        self.lrc ^= value & 0xF;
+        self
+    }
+
+    const fn push_digits(mut self, digits: &[u8]) -> Encoder {
+        let mut i = 0;
+        while i < digits.len() {
+            self = self.push(digits[i]);
+            i += 1;
+        }
+        self
+    }
+}
+
+const fn const_eq(a: &[u8; FASCN_LEN], b: &[u8; FASCN_LEN]) -> bool {
+    let mut i = 0;
+    while i < FASCN_LEN {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+// Example of the HID document above:
+// S 0032 F 0001 F 092446 F 0 F 1 F 1112223333 1 1223 2 E
+const _: () = assert!(const_eq(
+    &Fascn {
+        agency_code: [0, 0, 3, 2],
+        system_code: [0, 0, 0, 1],
+        credential_number: [0, 9, 2, 4, 4, 6],
+        credential_series: 0,
+        individual_credential_issue: 1,
+        person_identifier: [1, 1, 1, 2, 2, 2, 3, 3, 3, 3],
+        organizational_category: 1,
+        organizational_identifier: [1, 2, 2, 3],
+        person_association: 2,
+    }
+    .encode(),
+    &[
+        0xD0, 0x43, 0x94, 0x58, 0x21, 0x0C, 0x2C, 0x19, 0xA0, 0x84, 0x6D, 0x83, 0x68, 0x5A, 0x10,
+        0x82, 0x10, 0x8C, 0xE7, 0x39, 0x84, 0x10, 0x8C, 0xA3, 0xFC,
+    ],
+));
+
+// Value previously baked in the CHUID
+const _: () = assert!(const_eq(
+    &Fascn::DEFAULT.encode(),
+    &[
+        0xD4, 0xE7, 0x39, 0xDA, 0x73, 0x9C, 0xED, 0x39, 0xCE, 0x73, 0x9D, 0x83, 0x68, 0x58, 0x21,
+        0x08, 0x42, 0x10, 0x84, 0x21, 0xCC, 0x33, 0x9E, 0x23, 0xFF,
+    ],
+));
@@ -0,0 +1,196 @@
+use crate::slots::{self, SlotKey};
+use nanos_sdk::bindings::{
+    cx_aes_dec_block, cx_aes_enc_block, cx_aes_init_key_no_throw, cx_aes_key_t, cx_ecdh_no_throw,
+    cx_ecdsa_sign_no_throw, cx_ecfp_generate_pair_no_throw, cx_ecfp_private_key_t,
+    cx_ecfp_public_key_t, cx_hash_sha256,
+};
+use nanos_sdk::bindings::{CX_CURVE_SECP256R1, CX_LAST, CX_OK, CX_RND_RFC6979, CX_SHA256};
+use nanos_sdk::ecc::CurvesId;
+use nanos_sdk::io::SyscallError;
+use tinyvec::ArrayVec;
+
+// BIP32 Path for PIV
+// m/5261654'/<account>'/<usage>'/<key reference>'
+// 5261654 corresponds to "PIV" encoded in big-endian ASCII.
+const BIP32_PATH: [u32; 5] = nanos_sdk::ecc::make_bip32_path(b"m/5261654'/0'/0'/130'");
+
+// Usage index of keys stored in PIV key slots
+const USAGE_SLOT_KEY: u32 = 0;
+// Usage index of the key signing PIV data objects
+const USAGE_CONTENT_SIGNER: u32 = 1;
+// Usage index of the keys encrypting imported keys, one per slot
+const USAGE_KEY_WRAPPING: u32 = 2;
+// Usage index of the secret identifying the seed, see `seed_identifier`
+const USAGE_SEED_IDENTIFIER: u32 = 3;
+
+const HARDENED: u32 = 0x8000_0000;
+
+// Cryptographic algorithm identifiers
+// (https://nvlpubs.nist.gov/nistpubs/SpecialPublications/NIST.SP.800-78-4.pdf, Table 6-2)
+pub const ALGORITHM_3DES: u8 = 0x03;
+pub const ALGORITHM_ECC_P256: u8 = 0x11;
+
+// Algorithms of the management key and of the slot keys
+pub const SUPPORTED_ALGORITHMS: [u8; 2] = [ALGORITHM_3DES, ALGORITHM_ECC_P256];
+
+// Key reference of the attestation key, a slot key signing attestations of
+// the other slot keys
+pub const ATTESTATION_KEY_REF: u8 = 0xF9;
+
+pub const PRIVATE_KEY_LEN: usize = 32;
+pub const PUBLIC_KEY_LEN: usize = 65;
+pub const ECDSA_SIGNATURE_MAX_LEN: usize = 72;
+
+/// BIP32 path of a key, see `BIP32_PATH`
+fn derivation_path(usage: u32, key_ref: u8) -> [u32; 5] {
+    let mut path = BIP32_PATH;
+    path[2] = HARDENED | usage;
+    path[3] = HARDENED | key_ref as u32;
+    path
+}
+
+/// Helper function that derives the seed over Secp256r1
+fn bip32_derive_secp256r1(path: &[u32]) -> Result<[u8; 32], SyscallError> {
+    let mut raw_key = [0u8; 32];
+    nanos_sdk::ecc::bip32_derive(CurvesId::Secp256r1, path, &mut raw_key)?;
+    Ok(raw_key)
+}
+
+// Order of the Secp256r1 group
+const SECP256R1_ORDER: [u8; PRIVATE_KEY_LEN] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xBC, 0xE6, 0xFA, 0xAD, 0xA7, 0x17, 0x9E, 0x84, 0xF3, 0xB9, 0xCA, 0xC2, 0xFC, 0x63, 0x25, 0x51,
+];
+
+/// Private key of a PIV key slot, imported by the host or derived from the
+/// seed
+///
+/// The slot must be populated, see `slots::is_populated`.
+pub fn slot_private_key(key_ref: u8) -> cx_ecfp_private_key_t {
+    let raw_key = match slots::slot_key(key_ref) {
+        Some(SlotKey::Imported(wrapped_key)) => unwrap_key(key_ref, &wrapped_key),
+        Some(SlotKey::Derived(index)) => {
+            bip32_derive_secp256r1(&derivation_path(USAGE_SLOT_KEY, index)).unwrap()
+        }
+        None => panic!("empty slot"),
+    };
+    nanos_sdk::ecc::ec_init_key(CurvesId::Secp256r1, &raw_key).unwrap()
+}
+
+/// Whether `raw_key` is a valid Secp256r1 private key, in [1, n - 1]
+pub fn is_valid_private_key(raw_key: &[u8; PRIVATE_KEY_LEN]) -> bool {
+    raw_key.iter().any(|b| *b != 0) && raw_key[..] < SECP256R1_ORDER[..]
+}
+
+/// AES key encrypting the imported key of a slot
+fn key_wrapping_key(key_ref: u8) -> cx_aes_key_t {
+    let raw_key = bip32_derive_secp256r1(&derivation_path(USAGE_KEY_WRAPPING, key_ref)).unwrap();
+    unsafe {
+        let mut key: cx_aes_key_t = core::mem::zeroed();
+        let err = cx_aes_init_key_no_throw(raw_key.as_ptr(), raw_key.len() as u32, &mut key);
+        assert_eq!(err, CX_OK);
+        key
+    }
+}
+
+/// Encrypt an imported private key before storing it in NVM
+pub fn wrap_key(key_ref: u8, raw_key: &[u8; PRIVATE_KEY_LEN]) -> [u8; PRIVATE_KEY_LEN] {
+    let key = key_wrapping_key(key_ref);
+    let mut wrapped_key = [0u8; PRIVATE_KEY_LEN];
+    for (input, output) in raw_key.chunks(16).zip(wrapped_key.chunks_mut(16)) {
+        let err = unsafe { cx_aes_enc_block(&key, input.as_ptr(), output.as_mut_ptr()) };
+        assert_eq!(err, CX_OK);
+    }
+    wrapped_key
+}
+
+/// Decrypt an imported private key, as encrypted by `wrap_key`
+pub fn unwrap_key(key_ref: u8, wrapped_key: &[u8; PRIVATE_KEY_LEN]) -> [u8; PRIVATE_KEY_LEN] {
+    let key = key_wrapping_key(key_ref);
+    let mut raw_key = [0u8; PRIVATE_KEY_LEN];
+    for (input, output) in wrapped_key.chunks(16).zip(raw_key.chunks_mut(16)) {
+        let err = unsafe { cx_aes_dec_block(&key, input.as_ptr(), output.as_mut_ptr()) };
+        assert_eq!(err, CX_OK);
+    }
+    raw_key
+}
+
+/// Identifier of the seed, which does not reveal any key
+pub fn seed_identifier() -> [u8; 32] {
+    let secret = bip32_derive_secp256r1(&derivation_path(USAGE_SEED_IDENTIFIER, 0)).unwrap();
+    sha256(&secret)
+}
+
+/// Private key signing the data objects served by the card (CHUID, ...)
+pub fn content_signer_private_key() -> cx_ecfp_private_key_t {
+    let raw_key = bip32_derive_secp256r1(&derivation_path(USAGE_CONTENT_SIGNER, 0)).unwrap();
+    nanos_sdk::ecc::ec_init_key(CurvesId::Secp256r1, &raw_key).unwrap()
+}
+
+/// Uncompressed public point of a Secp256r1 private key
+pub fn public_key(pvkey: &cx_ecfp_private_key_t) -> [u8; PUBLIC_KEY_LEN] {
+    let mut pvkey = *pvkey;
+    let mut pubkey = cx_ecfp_public_key_t {
+        curve: CX_CURVE_SECP256R1,
+        W_len: 0,
+        W: [0u8; PUBLIC_KEY_LEN],
+    };
+    let err = unsafe {
+        cx_ecfp_generate_pair_no_throw(CX_CURVE_SECP256R1, &mut pubkey, &mut pvkey, true)
+    };
+    assert_eq!(err, CX_OK);
+    pubkey.W
+}
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut digest = [0u8; 32];
+    unsafe {
+        cx_hash_sha256(
+            data.as_ptr(),
+            data.len() as u32,
+            digest.as_mut_ptr(),
+            digest.len() as u32,
+        );
+    }
+    digest
+}
+
+/// ECDSA signature (DER encoded) of the SHA-256 digest of `data`
+pub fn sign_sha256(
+    pvkey: &cx_ecfp_private_key_t,
+    data: &[u8],
+) -> ArrayVec<[u8; ECDSA_SIGNATURE_MAX_LEN]> {
+    let digest = sha256(data);
+    let mut signature = [0u8; ECDSA_SIGNATURE_MAX_LEN];
+    let mut signature_len = signature.len() as u32;
+    let mut info = 0u32;
+    let err = unsafe {
+        cx_ecdsa_sign_no_throw(
+            pvkey,
+            CX_RND_RFC6979 | CX_LAST,
+            CX_SHA256,
+            digest.as_ptr(),
+            digest.len() as u32,
+            signature.as_mut_ptr(),
+            &mut signature_len,
+            &mut info,
+        )
+    };
+    assert_eq!(err, CX_OK);
+
+    let mut res = ArrayVec::new();
+    res.extend_from_slice(&signature[..signature_len as usize]);
+    res
+}
+
+pub fn ecdh(pvkey: &cx_ecfp_private_key_t, mode: u32, p: &[u8], p_len: u32) -> Option<[u8; 0x20]> {
+    let mut secret = [0u8; 0x20];
+    //let secret_len = &mut (secret.len() as u32);
+    let len =
+        unsafe { cx_ecdh_no_throw(pvkey, mode, p.as_ptr(), p_len, secret.as_mut_ptr(), 0x20) };
+    if len != CX_OK {
+        None
+    } else {
+        Some(secret)
+    }
+}
@@ -1,92 +1,138 @@
 #![no_std]
 #![no_main]
 
-use nanos_sdk::bindings::cx_ecdh_no_throw;
-use nanos_sdk::bindings::cx_ecfp_private_key_t;
-use nanos_sdk::bindings::{os_global_pin_is_validated, os_serial};
-use nanos_sdk::bindings::{CX_ECDH_POINT, CX_OK};
+use core::ptr::addr_of_mut;
+use nanos_sdk::bindings::CX_ECDH_POINT;
+use nanos_sdk::bindings::{os_global_pin_is_validated, os_global_pin_retries};
 use nanos_sdk::buttons::ButtonEvent;
-use nanos_sdk::ecc::CurvesId;
 use nanos_sdk::io;
-use nanos_sdk::io::SyscallError;
+use tinyvec::ArrayVec;
 
 mod bitmaps;
+mod cms;
 mod data_object;
 mod data_response;
+mod der;
+mod fascn;
 mod fonts;
+mod keys;
 mod layout;
 mod screen_util;
+mod security;
+mod settings;
+mod slots;
 mod status;
+mod storage;
+mod tlv;
+mod ui;
 mod utils;
+mod x509;
 
 use data_object::*;
 use data_response::*;
-use layout::*;
+use security::CardState;
 use status::*;
 
 nanos_sdk::set_panic!(nanos_sdk::exiting_panic);
 
 // PIV Application ID
 // (https://nvlpubs.nist.gov/nistpubs/specialpublications/nist.sp.800-73-4.pdf, 2.2)
-// Right truncated version
-const PIV_AID: [u8; 9] = [0xa0, 0x00, 0x00, 0x03, 0x08, 0x00, 0x00, 0x10, 0x00];
-
-// BIP32 Path for PIV
-// m/5261654'/<account>'/<usage>'/<key reference>'
-// 5261654 corresponds to "PIV" encoded in big-endian ASCII.
-const BIP32_PATH: [u32; 5] = nanos_sdk::ecc::make_bip32_path(b"m/5261654'/0'/0'/130'");
-
-/// Helper function that derives the seed over Secp256r1
-fn bip32_derive_secp256r1(path: &[u32]) -> Result<[u8; 32], SyscallError> {
-    let mut raw_key = [0u8; 32];
-    nanos_sdk::ecc::bip32_derive(CurvesId::Secp256r1, path, &mut raw_key)?;
-    Ok(raw_key)
-}
-
-fn ecdh(pvkey: &cx_ecfp_private_key_t, mode: u32, p: &[u8], p_len: u32) -> Option<([u8; 0x20])> {
-    let mut secret = [0u8; 0x20];
-    //let secret_len = &mut (secret.len() as u32);
-    let len =
-        unsafe { cx_ecdh_no_throw(pvkey, mode, p.as_ptr(), p_len, secret.as_mut_ptr(), 0x20) };
-    if len != CX_OK {
-        None
-    } else {
-        Some(secret)
-    }
+//
+// NIST RID followed by the PIX of the PIV application, ending with its
+// version. Hosts usually select it with a right-truncated version.
+const PIV_AID: [u8; 11] = [
+    0xa0, 0x00, 0x00, 0x03, 0x08, 0x00, 0x00, 0x10, 0x00, 0x01, 0x00,
+];
+const NIST_RID_LEN: usize = 5;
+
+// Application Property Template
+// (https://nvlpubs.nist.gov/nistpubs/specialpublications/nist.sp.800-73-4.pdf, Table 3)
+const APPLICATION_LABEL: &str = "Ledger PIV";
+const APPLICATION_URL: &str = "https://github.com/aiooss-ledger/ledger-nano-piv";
+
+/// Whether a (possibly right-truncated) AID selects the PIV application
+///
+/// At least the RID must be given (ISO 7816-4, 12.2.3).
+fn matches_piv_aid(aid: &[u8]) -> bool {
+    aid.len() >= NIST_RID_LEN && PIV_AID.starts_with(aid)
 }
 
 /// Select card command
-fn process_select_card(comm: &mut io::Comm) {
-    if comm.get_p1() != 0x04 || comm.get_p2() != 0x00 {
+///
+/// Only selection by AID is supported. As the PIV application is the only
+/// one, there is no next or previous occurrence.
+fn process_select_card(comm: &mut io::Comm, card_state: &mut CardState) {
+    let p2 = comm.get_p2();
+    if comm.get_p1() != 0x04 {
         return comm.reply(StatusWord::IncorrectP1P2);
     }
-    if let Ok(d) = comm.get_data() {
-        if d != PIV_AID {
-            return comm.reply(StatusWord::WrongData);
+
+    let aid = match comm.get_data() {
+        Ok(d) if !d.is_empty() => d,
+        _ => return comm.reply(StatusWord::WrongLength),
+    };
+    if !matches_piv_aid(aid) {
+        card_state.deselect();
+        return comm.reply(StatusWord::FileNotFound);
+    }
+
+    // First or last occurrence, then FCI or no response data
+    match (p2 & 0x03, p2 & 0xFC) {
+        (0x00 | 0x01, 0x00) => card_state.select(),
+        (0x00 | 0x01, 0x0C) => {
+            card_state.select();
+            return comm.reply_ok();
         }
+        (0x02 | 0x03, 0x00 | 0x0C) => return comm.reply(StatusWord::FileNotFound),
+        _ => return comm.reply(StatusWord::IncorrectP1P2),
+    }
+
+    let mut apt = ArrayVec::<[u8; 128]>::new();
+    let mut w = der::DerWriter::new(&mut apt);
+    let template = w.open(0x61);
+    w.write(0x4F, &PIV_AID[NIST_RID_LEN..]);
+    let authority = w.open(0x79);
+    w.write(0x4F, &PIV_AID[..NIST_RID_LEN]);
+    w.close(authority);
+    w.write(0x50, APPLICATION_LABEL.as_bytes());
+    w.write(0x5F50, APPLICATION_URL.as_bytes());
+    let algorithms = w.open(0xAC);
+    for algorithm in keys::SUPPORTED_ALGORITHMS {
+        w.write(0x80, &[algorithm]);
+    }
+    w.write(0x06, &[]);
+    w.close(algorithms);
+    w.close(template);
+    if let Err(s) = w.finish() {
+        return comm.reply(s);
     }
 
-    comm.append(&[
-        0x61, 0x11, 0x4f, 0x06, 0x00, 0x00, 0x10, 0x00, 0x01, 0x00, 0x79, 0x07, 0x4f, 0x05,
-    ]);
-    comm.append(&PIV_AID);
+    comm.append(&apt);
     comm.reply_ok();
 }
 
 /// General Authenticate card command
-fn process_general_auth(comm: &mut io::Comm) {
+fn process_general_auth(comm: &mut io::Comm, card_state: &mut CardState) {
     let alg = comm.get_p1();
     let key = comm.get_p2();
 
+    // PIV Card Application Administration Key
+    if key == 0x9B {
+        return security::process_mgmt_auth(comm, card_state);
+    }
+
     // Right now, we only support Secp256r1
-    if alg != 0x11 {
+    if alg != keys::ALGORITHM_ECC_P256 {
         return comm.reply(StatusWord::FuncNotSupported);
     }
 
     // Right now, we only support retired slots
-    if !(0x82..=0x8C).contains(&key) {
+    if !slots::RETIRED_SLOTS.contains(&key) {
         return comm.reply(StatusWord::FuncNotSupported);
     }
+    if !slots::is_populated(key) {
+        return comm.reply(StatusWord::RefDataNotFound);
+    }
 
     let d = match comm.get_data() {
         Ok(d) => d,
@@ -119,16 +165,22 @@ fn process_general_auth(comm: &mut io::Comm) {
         return comm.reply(StatusWord::WrongData);
     }
 
-    let d = &d[2..];
-    // EC point
-    if d[0] != 0x04 {
+    // EC point, kept as the command data may be overwritten while the key use
+    // is approved
+    let point: [u8; 0x41] = match d[2..].try_into() {
+        Ok(point) => point,
+        Err(_) => return comm.reply(StatusWord::WrongData),
+    };
+    if point[0] != 0x04 {
         return comm.reply(StatusWord::WrongData);
     }
 
-    let raw_key = bip32_derive_secp256r1(&BIP32_PATH).unwrap();
-    let pk = nanos_sdk::ecc::ec_init_key(CurvesId::Secp256r1, &raw_key).unwrap();
+    if let Err(s) = card_state.use_key(comm, key) {
+        return comm.reply(s);
+    }
+    let pk = keys::slot_private_key(key);
 
-    let secret = ecdh(&pk, CX_ECDH_POINT, d, 0x41).unwrap();
+    let secret = keys::ecdh(&pk, CX_ECDH_POINT, &point, 0x41).unwrap();
 
     comm.append(&[0x7c, 0x22, 0x82, 0x20]);
     comm.append(&secret);
@@ -163,7 +215,11 @@ fn check_get_data_params(data: &[u8]) -> Result<(), StatusWord> {
     Ok(())
 }
 
-fn process_get_data(comm: &mut io::Comm, response_buffer: &mut DataResponseBuffer) {
+fn process_get_data(
+    comm: &mut io::Comm,
+    response_buffer: &mut DataResponseBuffer,
+    card_state: &CardState,
+) {
     if comm.get_p1() != 0x3F || comm.get_p2() != 0xFF {
         return comm.reply(StatusWord::IncorrectP1P2);
     }
@@ -180,23 +236,409 @@ fn process_get_data(comm: &mut io::Comm, response_buffer: &mut DataResponseBuffe
         return comm.reply(status);
     }
 
-    match DataObjectIdentifier::from(&data[2..]).handle(response_buffer) {
+    let object = DataObjectIdentifier::from(&data[2..]);
+    if object.read_access() == AccessRule::Pin && !card_state.pin_verified {
+        return comm.reply(StatusWord::SecurityStatusNotSatisfied);
+    }
+
+    match object.handle(response_buffer) {
+        Ok(()) => response_buffer.send(comm),
+        Err(s) => comm.reply(s),
+    }
+}
+
+/// Put data card command
+///
+/// Command data is the '5C' tag list identifying the object, followed by its
+/// new content in a '53' tag.
+/// Large objects are sent with command chaining, the content of the following
+/// commands of the chain is appended to the object.
+fn process_put_data(
+    comm: &mut io::Comm,
+    card_state: &CardState,
+    pending_put: &mut Option<storage::Writer>,
+) {
+    // More commands follow in the chain (ISO 7816-4, 5.1.1)
+    let chained = comm.apdu_buffer[0] & 0x10 != 0;
+    let writer = pending_put.take();
+
+    if comm.get_p1() != 0x3F || comm.get_p2() != 0xFF {
+        return comm.reply(StatusWord::IncorrectP1P2);
+    }
+
+    // Following commands of a chain continue the approved operation
+    if writer.is_none() {
+        if let Err(s) = security::authorize_admin(comm, card_state, "Write data") {
+            return comm.reply(s);
+        }
+    }
+
+    let data = match comm.get_data() {
+        Ok(d) => d,
+        Err(e) => {
+            return comm.reply(e);
+        }
+    };
+
+    let result = match writer {
+        Some(mut writer) => writer.append(data).map(|()| Some(writer)),
+        None => start_put_data(data),
+    };
+
+    match result {
+        Ok(Some(writer)) if !writer.is_complete() => {
+            if !chained {
+                return comm.reply(StatusWord::WrongLength);
+            }
+            *pending_put = Some(writer);
+            comm.reply_ok();
+        }
+        Ok(_) => comm.reply_ok(),
+        Err(s) => comm.reply(s),
+    }
+}
+
+/// Parse the first command of a 'put data' chain, and start writing the object
+fn start_put_data(data: &[u8]) -> Result<Option<storage::Writer>, StatusWord> {
+    let (tag_list, rest) = tlv::parse(data).ok_or(StatusWord::WrongData)?;
+    if tag_list.tag != 0x5C {
+        return Err(StatusWord::WrongData);
+    }
+
+    match tlv::parse_header(rest) {
+        Some((0x53, len, header_len)) => {
+            DataObjectIdentifier::from(tag_list.value).put(len, &rest[header_len..])
+        }
+        _ => Err(StatusWord::WrongData),
+    }
+}
+
+/// Generate a PKCS#10 Certificate Signing Request for a primary slot key
+///
+/// Command data is the DER encoded subject Name, optionally followed by the
+/// DER encoded Extensions to request.
+fn process_generate_csr(comm: &mut io::Comm, response_buffer: &mut DataResponseBuffer) {
+    let alg = comm.get_p1();
+    let key = comm.get_p2();
+
+    // Right now, we only support Secp256r1
+    if alg != keys::ALGORITHM_ECC_P256 {
+        return comm.reply(StatusWord::FuncNotSupported);
+    }
+
+    if slots::primary_index(key).is_none() {
+        return comm.reply(StatusWord::IncorrectP1P2);
+    }
+    if !slots::is_populated(key) {
+        return comm.reply(StatusWord::RefDataNotFound);
+    }
+
+    if let Err(status) = parse_csr_params(comm) {
+        return comm.reply(status);
+    }
+
+    if !ui::confirm_slot(comm, "*Sign CSR*", key) {
+        return comm.reply(StatusWord::ConditionsNotSatisfied);
+    }
+
+    let (subject, extensions) = match parse_csr_params(comm) {
+        Ok(params) => params,
+        Err(status) => return comm.reply(status),
+    };
+    let pvkey = keys::slot_private_key(key);
+
+    let mut w = response_buffer.der_writer();
+    x509::write_csr(&mut w, &pvkey, subject, extensions);
+    match w.finish() {
+        Ok(()) => response_buffer.send(comm),
+        Err(s) => comm.reply(s),
+    }
+}
+
+/// Split 'generate CSR' command data into subject and extensions
+fn parse_csr_params(comm: &io::Comm) -> Result<(&[u8], Option<&[u8]>), StatusWord> {
+    let data = comm.get_data().map_err(|_| StatusWord::WrongLength)?;
+
+    let (subject, rest) = tlv::parse(data).ok_or(StatusWord::WrongData)?;
+    if subject.tag != 0x30 {
+        return Err(StatusWord::WrongData);
+    }
+    if rest.is_empty() {
+        return Ok((subject.raw, None));
+    }
+
+    match tlv::parse(rest) {
+        Some((extensions, [])) if extensions.tag == 0x30 => Ok((subject.raw, Some(extensions.raw))),
+        _ => Err(StatusWord::WrongData),
+    }
+}
+
+/// Update application settings (vendor command)
+///
+/// Command data is a list of TLV, one per setting to change:
+///  - 01: FASC-N fields as 32 ASCII digits, in encoding order
+///  - 02: CHUID expiration date as YYYYMMDD ASCII digits
+///  - 03: number of retired keys with an off-card certificate
+///  - 04: URL of the off-card certificates, empty to remove it
+///  - 05: PIN policy, 00 for the PIV PIN only, 01 to also accept the Global
+///    PIN and 02 to make the Global PIN the primary one
+///  - 06: card identity, 00 for the device serial number, 01 for the seed
+///    and 4 bytes for a serial number set by the user
+///  - 07: Yubico firmware version reported to tools, as 3 bytes, empty for
+///    the implemented one
+fn process_configure(comm: &mut io::Comm) {
+    if comm.get_p1() != 0x00 || comm.get_p2() != 0x00 {
+        return comm.reply(StatusWord::IncorrectP1P2);
+    }
+
+    if let Err(status) = parse_settings(comm) {
+        return comm.reply(status);
+    }
+
+    if !ui::confirm(comm, "*Update*", "card settings") {
+        return comm.reply(StatusWord::ConditionsNotSatisfied);
+    }
+
+    match parse_settings(comm) {
+        Ok(new_settings) => {
+            settings::set(&new_settings);
+            comm.reply_ok();
+        }
+        Err(status) => comm.reply(status),
+    }
+}
+
+/// Apply 'configure' command data to current settings
+fn parse_settings(comm: &io::Comm) -> Result<settings::Settings, StatusWord> {
+    let mut data = comm.get_data().map_err(|_| StatusWord::WrongLength)?;
+    let mut new_settings = *settings::get();
+
+    while !data.is_empty() {
+        let (setting, rest) = tlv::parse(data).ok_or(StatusWord::WrongData)?;
+        match setting.tag {
+            0x01 => {
+                new_settings.fascn =
+                    fascn::Fascn::from_digits(setting.value).ok_or(StatusWord::WrongData)?;
+            }
+            0x02 => {
+                new_settings.chuid_expiry =
+                    utils::parse_date(setting.value).ok_or(StatusWord::WrongData)?;
+            }
+            0x03 => match setting.value {
+                [count] => new_settings.off_card_certificates = *count,
+                _ => return Err(StatusWord::WrongData),
+            },
+            0x04 => {
+                let url = setting.value;
+                if url.len() > settings::OFF_CARD_URL_MAX_LEN || !url.is_ascii() {
+                    return Err(StatusWord::WrongData);
+                }
+                new_settings.off_card_url[..url.len()].copy_from_slice(url);
+                new_settings.off_card_url_len = url.len();
+            }
+            0x05 => match setting.value {
+                [policy] => {
+                    new_settings.pin_policy =
+                        settings::PinPolicy::from_u8(*policy).ok_or(StatusWord::WrongData)?;
+                }
+                _ => return Err(StatusWord::WrongData),
+            },
+            0x06 => {
+                new_settings.serial_source = settings::SerialSource::from_bytes(setting.value)
+                    .ok_or(StatusWord::WrongData)?;
+            }
+            0x07 => match setting.value {
+                [] => new_settings.yubico_version = None,
+                [major, minor, patch] => {
+                    new_settings.yubico_version = Some([*major, *minor, *patch]);
+                }
+                _ => return Err(StatusWord::WrongData),
+            },
+            _ => return Err(StatusWord::WrongData),
+        }
+        data = rest;
+    }
+
+    // Off-card certificates must be retrievable, and each one takes a retired
+    // slot
+    let retired = slots::retired_on_card() as usize + new_settings.off_card_certificates as usize;
+    if (new_settings.off_card_certificates != 0 && new_settings.off_card_url_len == 0)
+        || retired > slots::RETIRED_SLOTS.len()
+    {
+        return Err(StatusWord::WrongData);
+    }
+
+    Ok(new_settings)
+}
+
+/// Attest that a slot key was generated by the card
+///
+/// Answers an X.509 certificate of the slot key, issued by the attestation
+/// key whose certificate is the 5FFF01 object.
+fn process_attest(comm: &mut io::Comm, response_buffer: &mut DataResponseBuffer) {
+    let key = comm.get_p1();
+    if comm.get_p2() != 0x00 {
+        return comm.reply(StatusWord::IncorrectP1P2);
+    }
+
+    if key == keys::ATTESTATION_KEY_REF || !slots::is_populated(key) {
+        return comm.reply(StatusWord::RefDataNotFound);
+    }
+    // Only keys generated by the card can be attested
+    if slots::origin(key) != slots::ORIGIN_GENERATED {
+        return comm.reply(StatusWord::ConditionsNotSatisfied);
+    }
+
+    let mut w = response_buffer.der_writer();
+    x509::write_attestation_certificate(
+        &mut w,
+        key,
+        &utils::card_serial(),
+        &yubico_version(),
+        &[slots::pin_policy(key), slots::TOUCH_POLICY_NEVER],
+    );
+    match w.finish() {
         Ok(()) => response_buffer.send(comm),
         Err(s) => comm.reply(s),
     }
 }
 
-/// Get ledger serial
-const LEDGER_SERIAL_SIZE: usize = 7;
+/// Import an asymmetric key in a slot, replacing the key derived from the
+/// seed
+///
+/// Command data is the private key in a '06' tag, optionally followed by
+/// the PIN ('AA') and touch ('AB') policies. The touch policy can only be
+/// left to its default.
+/// Only P-256 keys can be imported, the slots do not support other
+/// algorithms.
+fn process_import(comm: &mut io::Comm, card_state: &CardState) {
+    let alg = comm.get_p1();
+    let key = comm.get_p2();
+
+    // Right now, we only support Secp256r1
+    if alg != keys::ALGORITHM_ECC_P256 {
+        return comm.reply(StatusWord::FuncNotSupported);
+    }
+    if !slots::can_replace(key) {
+        return comm.reply(StatusWord::IncorrectP1P2);
+    }
+    if !slots::can_fill(key) {
+        return comm.reply(StatusWord::ConditionsNotSatisfied);
+    }
+
+    if let Err(s) = parse_import_params(comm, key) {
+        return comm.reply(s);
+    }
+
+    if let Err(s) = security::authorize_admin(comm, card_state, "Import key") {
+        return comm.reply(s);
+    }
+
+    match parse_import_params(comm, key) {
+        Ok((raw_key, pin_policy)) => {
+            slots::import_key(key, &raw_key, pin_policy);
+            comm.reply_ok();
+        }
+        Err(s) => comm.reply(s),
+    }
+}
+
+/// Extract the private key and its PIN policy from 'import' command data
+fn parse_import_params(
+    comm: &io::Comm,
+    key: u8,
+) -> Result<([u8; keys::PRIVATE_KEY_LEN], u8), StatusWord> {
+    let mut data = comm.get_data().map_err(|_| StatusWord::WrongLength)?;
+
+    let mut raw_key = None;
+    let mut pin_policy = slots::default_pin_policy(key);
+    while !data.is_empty() {
+        let (param, rest) = tlv::parse(data).ok_or(StatusWord::WrongData)?;
+        match (param.tag, param.value) {
+            (0x06, value) => raw_key = Some(value.try_into().map_err(|_| StatusWord::WrongData)?),
+            (0xAA, [0x00]) => (),
+            (0xAA, [policy @ slots::PIN_POLICY_NEVER..=slots::PIN_POLICY_ALWAYS]) => {
+                pin_policy = *policy
+            }
+            (0xAB, [0x00 | slots::TOUCH_POLICY_NEVER]) => (),
+            _ => return Err(StatusWord::WrongData),
+        }
+        data = rest;
+    }
+
+    match raw_key {
+        Some(raw_key) if keys::is_valid_private_key(&raw_key) => Ok((raw_key, pin_policy)),
+        _ => Err(StatusWord::WrongData),
+    }
+}
+
+/// Move the key of a slot to another slot, or delete it
+///
+/// P1 is the destination slot, FF to delete the key, and P2 the source slot.
+/// The certificate written for the key follows it, so that the Key History
+/// and the retired certificate objects describe the keys actually held.
+/// Retired slots stay populated in order: only the last populated one can be
+/// emptied, and only the one following it filled.
+fn process_move_key(comm: &mut io::Comm, card_state: &CardState) {
+    let to = comm.get_p1();
+    let from = comm.get_p2();
+    let delete = to == 0xFF;
+
+    if !slots::can_replace(from) || !(delete || slots::can_replace(to) && to != from) {
+        return comm.reply(StatusWord::IncorrectP1P2);
+    }
+    if !slots::is_populated(from) {
+        return comm.reply(StatusWord::RefDataNotFound);
+    }
+    if !delete && slots::is_populated(to) {
+        return comm.reply(StatusWord::ConditionsNotSatisfied);
+    }
+    // Retired slots stay populated in order, a key moved between two of them
+    // would leave the first one empty
+    let between_retired = [from, to].iter().all(|k| slots::RETIRED_SLOTS.contains(k));
+    if !slots::can_empty(from) || !delete && (!slots::can_fill(to) || between_retired) {
+        return comm.reply(StatusWord::ConditionsNotSatisfied);
+    }
+
+    let operation = if delete { "Delete key" } else { "Move key" };
+    if let Err(s) = security::authorize_admin(comm, card_state, operation) {
+        return comm.reply(s);
+    }
+
+    let result = if delete {
+        data_object::delete_slot_certificate(from).map(|()| slots::delete_key(from))
+    } else {
+        data_object::move_slot_certificate(from, to).map(|()| slots::move_key(from, to))
+    };
+    match result {
+        Ok(()) => comm.reply_ok(),
+        Err(s) => comm.reply(s),
+    }
+}
 
-fn get_ledger_serial() -> [u8; LEDGER_SERIAL_SIZE] {
-    let mut serial = [0_u8; LEDGER_SERIAL_SIZE];
+/// Reset the PIV application to its factory state
+///
+/// Yubico requires the PIN and PUK to be blocked beforehand. As the PIN is the
+/// device PIN, whose blocking wipes the device, and there is no PUK, the reset
+/// is approved on the device instead.
+/// Settings, stored objects, imported keys and the management key are erased,
+/// but keys derived from the seed are not, and come back identical.
+fn process_reset(comm: &mut io::Comm, card_state: &mut CardState) {
+    if comm.get_p1() != 0x00 || comm.get_p2() != 0x00 {
+        return comm.reply(StatusWord::IncorrectP1P2);
+    }
 
-    unsafe {
-        os_serial(serial.as_mut_ptr(), LEDGER_SERIAL_SIZE as u32);
+    ui::notice(comm, "Keys derived from", "seed are kept");
+    if !ui::confirm(comm, "*Reset PIV*", "settings and data") {
+        return comm.reply(StatusWord::ConditionsNotSatisfied);
     }
 
-    serial
+    settings::reset();
+    storage::erase_all();
+    slots::reset();
+    security::reset_mgmt_key();
+    card_state.select();
+    comm.reply_ok();
 }
 
 /// Get card serial
@@ -205,34 +647,135 @@ fn process_get_serial(comm: &mut io::Comm) {
         return comm.reply(StatusWord::IncorrectP1P2);
     }
 
-    let ldg_serial = get_ledger_serial();
-    let age_serial = [ldg_serial[0], ldg_serial[2], ldg_serial[4], ldg_serial[6]];
-
-    comm.append(&age_serial);
+    comm.append(&utils::card_serial());
     comm.reply_ok();
 }
 
+// YubiKey firmware versions introducing PIV features, sorted, and whether the
+// app implements them
+// (https://github.com/Yubico/yubikey-manager/blob/main/yubikit/piv.py)
+const YUBICO_FEATURES: [([u8; 3], bool); 10] = [
+    // PIV applet of the YubiKey NEO
+    ([1, 0, 4], true),
+    // P-384 keys
+    ([4, 0, 0], false),
+    // Attestation
+    ([4, 3, 0], true),
+    // Cached touch policy
+    ([4, 3, 0], false),
+    // Serial number
+    ([5, 0, 0], true),
+    // Metadata
+    ([5, 3, 0], true),
+    // AES management keys
+    ([5, 4, 0], false),
+    // Moving and deleting keys
+    ([5, 7, 0], true),
+    // Ed25519 and X25519 keys
+    ([5, 7, 0], false),
+    // RSA 3072 and 4096 keys
+    ([5, 7, 0], false),
+];
+
+/// Highest firmware version whose features are all implemented by the app
+fn implemented_version() -> [u8; 3] {
+    let missing = YUBICO_FEATURES
+        .iter()
+        .filter(|(_, implemented)| !implemented)
+        .map(|(version, _)| *version)
+        .min()
+        .unwrap_or([0xFF; 3]);
+    YUBICO_FEATURES
+        .iter()
+        .filter(|(version, implemented)| *implemented && *version < missing)
+        .map(|(version, _)| *version)
+        .max()
+        .unwrap()
+}
+
+/// Firmware version reported to Yubico tools, unless set by the user
+fn yubico_version() -> [u8; 3] {
+    settings::get()
+        .yubico_version
+        .unwrap_or_else(implemented_version)
+}
+
 /// Get card version
 fn process_get_version(comm: &mut io::Comm) {
     if comm.get_p1() != 0x00 || comm.get_p2() != 0x00 {
         return comm.reply(StatusWord::IncorrectP1P2);
     }
 
-    // Same answer as Yubikey 5.4 firmware
-    comm.append(&[5, 4, 0]);
+    comm.append(&yubico_version());
     comm.reply_ok();
 }
 
-/// Verify PIV Card Application PIN
-fn process_verify(comm: &mut io::Comm) {
-    if comm.get_p1() != 0x00 || comm.get_p2() != 0x80 {
+// Number of attempts to enter the device PIN before the device is reset
+const DEVICE_PIN_MAX_RETRIES: u8 = 3;
+
+/// Get metadata of a slot key, of the PIN or of the management key
+///
+/// The device PIN stands for the PIN, there is no PUK.
+fn process_get_metadata(comm: &mut io::Comm) {
+    let key = comm.get_p2();
+    if comm.get_p1() != 0x00 {
         return comm.reply(StatusWord::IncorrectP1P2);
     }
 
+    let mut metadata = ArrayVec::<[u8; 128]>::new();
+    let mut w = der::DerWriter::new(&mut metadata);
+    match key {
+        // PIV Card Application PIN
+        0x80 => {
+            let retries = unsafe { os_global_pin_retries() } as u8;
+            w.write(0x01, &[0xFF]);
+            w.write(0x05, &[0x00]);
+            w.write(0x06, &[DEVICE_PIN_MAX_RETRIES, retries]);
+        }
+        // PIV Card Application Administration Key
+        0x9B => {
+            w.write(0x01, &[security::MGMT_KEY_ALGORITHM]);
+            w.write(0x02, &[0x00, security::mgmt_touch_policy()]);
+            w.write(0x05, &[security::is_default_mgmt_key() as u8]);
+        }
+        key if slots::is_populated(key) => {
+            let public_key = keys::public_key(&keys::slot_private_key(key));
+            w.write(0x01, &[keys::ALGORITHM_ECC_P256]);
+            w.write(0x02, &[slots::pin_policy(key), slots::TOUCH_POLICY_NEVER]);
+            w.write(0x03, &[slots::origin(key)]);
+            let public_key_template = w.open(0x04);
+            w.write(0x86, &public_key);
+            w.close(public_key_template);
+        }
+        _ => return comm.reply(StatusWord::RefDataNotFound),
+    }
+    if let Err(s) = w.finish() {
+        return comm.reply(s);
+    }
+
+    comm.append(&metadata);
+    comm.reply_ok();
+}
+
+/// Verify PIV Card Application PIN, or Global PIN when allowed by settings
+///
+/// Both are the device PIN, which is verified when unlocking the device.
+fn process_verify(comm: &mut io::Comm, card_state: &mut CardState) {
+    let global_pin_allowed = settings::get().pin_policy.global_pin_allowed();
+    match (comm.get_p1(), comm.get_p2()) {
+        (0x00, 0x80) => (),
+        (0x00, 0x00) if global_pin_allowed => (),
+        (0x00, 0x00) => return comm.reply(StatusWord::RefDataNotFound),
+        _ => return comm.reply(StatusWord::IncorrectP1P2),
+    }
+
     if unsafe { os_global_pin_is_validated() } != 0 {
+        card_state.pin_verified = true;
+        card_state.pin_just_verified = true;
         comm.reply_ok();
     } else {
-        comm.reply(StatusWord::VerificationFailed);
+        let retries = unsafe { os_global_pin_retries() } as u8;
+        comm.reply(StatusWord::VerificationFailed(retries));
     }
 }
 
@@ -246,13 +789,44 @@ extern "C" fn sample_main() {
     // When response is split across multiple APDU packets, remaining length to
     // read is sent to the host in the status word. The host ask the card to
     // continue the response with 0xC0 instruction.
-    let mut response_buffer = DataResponseBuffer::new();
+    //
+    // Static rather than on the stack, so that the linker checks it fits in RAM
+    static mut RESPONSE_BUFFER: DataResponseBuffer = DataResponseBuffer::new();
+    let response_buffer = unsafe { &mut *addr_of_mut!(RESPONSE_BUFFER) };
+
+    // Application selection, PIN and management key authentication state,
+    // lost when the app exits
+    let mut card_state = CardState::new();
+
+    // Object being written by a chain of 'put data' commands
+    let mut pending_put = None;
 
     // Increased every tick until standby. Resetted if a button is pressed.
     let mut standby_tick_count = 0;
 
     loop {
-        match comm.next_event() {
+        let event = comm.next_event();
+
+        if let io::Event::Command(ins) = event {
+            // Command chains are aborted by any other command
+            if ins != 0xDB {
+                pending_put = None;
+            }
+
+            // Keys with the "always" PIN policy can only be used right after
+            // the PIN is verified
+            if ins != 0x87 {
+                card_state.pin_just_verified = false;
+            }
+
+            // All commands but SELECT are addressed to the PIV application
+            if ins != 0xA4 && !card_state.is_selected() {
+                comm.reply(StatusWord::ConditionsNotSatisfied);
+                continue;
+            }
+        }
+
+        match event {
             io::Event::Button(ButtonEvent::BothButtonsRelease) => nanos_sdk::exit_app(0),
             io::Event::Button(_) => {
                 standby_tick_count = 0;
@@ -260,24 +834,34 @@ extern "C" fn sample_main() {
 
             // Standard PIV commands
             // See https://csrc.nist.gov/publications/detail/sp/800-73/4/final
-            io::Event::Command(0x20) => process_verify(&mut comm),
-            io::Event::Command(0xA4) => process_select_card(&mut comm),
-            io::Event::Command(0x87) => process_general_auth(&mut comm),
-            io::Event::Command(0xC0) => process_continue_response(&mut comm, &mut response_buffer),
-            io::Event::Command(0xCB) => process_get_data(&mut comm, &mut response_buffer),
+            io::Event::Command(0x20) => process_verify(&mut comm, &mut card_state),
+            io::Event::Command(0xA4) => process_select_card(&mut comm, &mut card_state),
+            io::Event::Command(0x87) => process_general_auth(&mut comm, &mut card_state),
+            io::Event::Command(0xC0) => process_continue_response(&mut comm, response_buffer),
+            io::Event::Command(0xCB) => process_get_data(&mut comm, response_buffer, &card_state),
+            io::Event::Command(0xDB) => process_put_data(&mut comm, &card_state, &mut pending_put),
 
             // YubicoPIV extensions
             // See https://developers.yubico.com/PIV/Introduction/Yubico_extensions.html
+            io::Event::Command(0xf6) => process_move_key(&mut comm, &card_state),
+            io::Event::Command(0xf7) => process_get_metadata(&mut comm),
             io::Event::Command(0xf8) => process_get_serial(&mut comm),
+            io::Event::Command(0xf9) => process_attest(&mut comm, response_buffer),
+            io::Event::Command(0xfb) => process_reset(&mut comm, &mut card_state),
             io::Event::Command(0xfd) => process_get_version(&mut comm),
+            io::Event::Command(0xfe) => process_import(&mut comm, &card_state),
+            io::Event::Command(0xff) => security::process_set_mgmt_key(&mut comm, &card_state),
+
+            // Vendor extensions
+            io::Event::Command(0xe0) => process_generate_csr(&mut comm, response_buffer),
+            io::Event::Command(0xe1) => process_configure(&mut comm),
 
             io::Event::Command(_) => comm.reply(StatusWord::FuncNotSupported),
 
             io::Event::Ticker => {
                 if standby_tick_count == 0 {
                     // Show message
-                    bitmaps::PADLOCK.draw(64 - (bitmaps::PADLOCK.width as i32) / 2, 4);
-                    "*PIV* ready".display(Line::Second, Layout::Centered);
+                    ui::ready_screen();
                 } else if standby_tick_count == 300 {
                     // Blank screen to mitigate burn-in effects
                     screen_util::fulldraw(0, 0, &bitmaps::BLANK);
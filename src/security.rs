@@ -0,0 +1,303 @@
+use crate::keys;
+use crate::slots;
+use crate::status::*;
+use crate::tlv;
+use crate::ui;
+use nanos_sdk::bindings::CX_OK;
+use nanos_sdk::bindings::{
+    cx_des_enc_block, cx_des_init_key_no_throw, cx_des_key_t, cx_rng_no_throw,
+};
+use nanos_sdk::io;
+use nanos_sdk::nvm::AtomicStorage;
+use nanos_sdk::NVMData;
+
+// PIV Card Application Administration Key (9B)
+// Triple DES, with the well-known default value until it is changed
+pub const MGMT_KEY_ALGORITHM: u8 = keys::ALGORITHM_3DES;
+const MGMT_KEY_LEN: usize = 24;
+const DEFAULT_MGMT_KEY: [u8; MGMT_KEY_LEN] = [
+    0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+    0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+];
+const MGMT_BLOCK_SIZE: usize = 8;
+
+/// Management key set with `process_set_mgmt_key`, persisted in NVM
+#[derive(Copy, Clone)]
+struct MgmtKeyRecord {
+    key: [u8; MGMT_KEY_LEN],
+    // Admin operations must be approved on the device, this is how the
+    // Yubico "touch required" policy is honoured
+    touch_required: bool,
+}
+
+impl MgmtKeyRecord {
+    const DEFAULT: MgmtKeyRecord = MgmtKeyRecord {
+        key: DEFAULT_MGMT_KEY,
+        touch_required: false,
+    };
+}
+
+#[link_section = ".nvm_data"]
+static mut MGMT_KEY: NVMData<AtomicStorage<MgmtKeyRecord>> =
+    NVMData::new(AtomicStorage::new(&MgmtKeyRecord::DEFAULT));
+
+fn mgmt_key() -> &'static MgmtKeyRecord {
+    unsafe { MGMT_KEY.get_mut().get_ref() }
+}
+
+/// Whether the management key still has its default value
+pub fn is_default_mgmt_key() -> bool {
+    mgmt_key().key == DEFAULT_MGMT_KEY
+}
+
+/// Yubico touch policy of the management key
+pub fn mgmt_touch_policy() -> u8 {
+    match mgmt_key().touch_required {
+        true => slots::TOUCH_POLICY_ALWAYS,
+        false => slots::TOUCH_POLICY_NEVER,
+    }
+}
+
+/// Restore the default management key, overwriting both copies kept by
+/// `AtomicStorage` so that the previous key is erased
+pub fn reset_mgmt_key() {
+    for _ in 0..2 {
+        unsafe { MGMT_KEY.get_mut().update(&MgmtKeyRecord::DEFAULT) };
+    }
+}
+
+/// Card state: whether the PIV application is selected, then its security
+/// status
+///
+/// The card is reset when the app starts. Selecting the PIV application
+/// resets its security status, selecting another application deselects it.
+pub struct CardState {
+    selected: bool,
+    pub pin_verified: bool,
+    // PIN verified by the previous command, as required by keys whose PIN
+    // policy is "always"
+    pub pin_just_verified: bool,
+    pub admin_authenticated: bool,
+    // Witness or challenge sent to the host, waiting for its answer
+    pending_challenge: Option<[u8; MGMT_BLOCK_SIZE]>,
+}
+
+impl CardState {
+    pub fn new() -> CardState {
+        Self {
+            selected: false,
+            pin_verified: false,
+            pin_just_verified: false,
+            admin_authenticated: false,
+            pending_challenge: None,
+        }
+    }
+
+    pub fn is_selected(&self) -> bool {
+        self.selected
+    }
+
+    /// PIV application selected, with a fresh security status
+    pub fn select(&mut self) {
+        *self = Self::new();
+        self.selected = true;
+    }
+
+    /// Another application selected
+    pub fn deselect(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Check that the key of a slot can be used according to its PIN policy,
+    /// the PIN verification of the previous command being consumed
+    ///
+    /// The PIN is verified when unlocking the device, verifying it again does
+    /// not involve the user: each use of a key whose PIN policy is "always"
+    /// must also be approved on the device.
+    pub fn use_key(&mut self, comm: &mut io::Comm, key_ref: u8) -> Result<(), StatusWord> {
+        let pin_just_verified = core::mem::take(&mut self.pin_just_verified);
+        let pin_policy = slots::pin_policy(key_ref);
+        let pin_satisfied = match pin_policy {
+            slots::PIN_POLICY_NEVER => true,
+            slots::PIN_POLICY_ONCE => self.pin_verified,
+            _ => pin_just_verified,
+        };
+        if !pin_satisfied {
+            return Err(StatusWord::SecurityStatusNotSatisfied);
+        }
+        if pin_policy == slots::PIN_POLICY_ALWAYS && !ui::confirm_slot(comm, "*Use key*", key_ref) {
+            return Err(StatusWord::ConditionsNotSatisfied);
+        }
+        Ok(())
+    }
+}
+
+fn random_block() -> [u8; MGMT_BLOCK_SIZE] {
+    let mut block = [0u8; MGMT_BLOCK_SIZE];
+    unsafe { cx_rng_no_throw(block.as_mut_ptr(), block.len() as u32) };
+    block
+}
+
+fn encrypt_block(block: &[u8; MGMT_BLOCK_SIZE]) -> [u8; MGMT_BLOCK_SIZE] {
+    let mut out = [0u8; MGMT_BLOCK_SIZE];
+    unsafe {
+        let mut key: cx_des_key_t = core::mem::zeroed();
+        let mgmt_key = &mgmt_key().key;
+        let err = cx_des_init_key_no_throw(mgmt_key.as_ptr(), mgmt_key.len() as u32, &mut key);
+        assert_eq!(err, CX_OK);
+        cx_des_enc_block(&key, block.as_ptr(), out.as_mut_ptr());
+    }
+    out
+}
+
+/// Reply with a single dynamic authentication template element
+fn reply_template(comm: &mut io::Comm, tag: u8, block: &[u8; MGMT_BLOCK_SIZE]) {
+    comm.append(&[0x7C, MGMT_BLOCK_SIZE as u8 + 2, tag, MGMT_BLOCK_SIZE as u8]);
+    comm.append(block);
+    comm.reply_ok();
+}
+
+/// General Authenticate with the PIV Card Application Administration Key
+///
+/// Supports both mutual authentication (witness 80, then challenge 81) and
+/// external authentication (challenge 81, then response 82).
+pub fn process_mgmt_auth(comm: &mut io::Comm, state: &mut CardState) {
+    if comm.get_p1() != MGMT_KEY_ALGORITHM {
+        return comm.reply(StatusWord::IncorrectP1P2);
+    }
+
+    // Any authentication attempt resets the previous one
+    state.admin_authenticated = false;
+    let pending_challenge = state.pending_challenge.take();
+
+    let data = match comm.get_data() {
+        Ok(d) => d,
+        Err(_) => return comm.reply(StatusWord::WrongData),
+    };
+    let template = match tlv::parse(data) {
+        Some((template, [])) if template.tag == 0x7C => template.value,
+        _ => return comm.reply(StatusWord::WrongData),
+    };
+
+    let mut witness = None;
+    let mut challenge = None;
+    let mut response = None;
+    let mut elements = template;
+    while !elements.is_empty() {
+        let (element, rest) = match tlv::parse(elements) {
+            Some(e) => e,
+            None => return comm.reply(StatusWord::WrongData),
+        };
+        match element.tag {
+            0x80 => witness = Some(element.value),
+            0x81 => challenge = Some(element.value),
+            0x82 => response = Some(element.value),
+            _ => return comm.reply(StatusWord::WrongData),
+        }
+        elements = rest;
+    }
+
+    match (witness, challenge, response) {
+        // Host requests a witness
+        (Some([]), None, None) => {
+            let witness = random_block();
+            state.pending_challenge = Some(witness);
+            reply_template(comm, 0x80, &encrypt_block(&witness));
+        }
+        // Host returns the decrypted witness and sends its own challenge
+        (Some(witness), Some(challenge), None) => {
+            match (
+                pending_challenge,
+                <[u8; MGMT_BLOCK_SIZE]>::try_from(challenge),
+            ) {
+                (Some(expected), Ok(challenge)) if witness == expected => {
+                    state.admin_authenticated = true;
+                    reply_template(comm, 0x82, &encrypt_block(&challenge));
+                }
+                _ => comm.reply(StatusWord::SecurityStatusNotSatisfied),
+            }
+        }
+        // Host requests a challenge
+        (None, Some([]), None) => {
+            let challenge = random_block();
+            state.pending_challenge = Some(challenge);
+            reply_template(comm, 0x81, &challenge);
+        }
+        // Host returns the encrypted challenge
+        (None, None, Some(response)) => match pending_challenge {
+            Some(challenge) if response == encrypt_block(&challenge) => {
+                state.admin_authenticated = true;
+                comm.reply_ok();
+            }
+            _ => comm.reply(StatusWord::SecurityStatusNotSatisfied),
+        },
+        _ => comm.reply(StatusWord::WrongData),
+    }
+}
+
+/// Check that an admin operation can be performed: the management key must
+/// be authenticated, and the operation approved on the device when the
+/// management key requires touch
+pub fn authorize_admin(
+    comm: &mut io::Comm,
+    state: &CardState,
+    operation: &str,
+) -> Result<(), StatusWord> {
+    if !state.admin_authenticated {
+        return Err(StatusWord::SecurityStatusNotSatisfied);
+    }
+    if mgmt_key().touch_required && !ui::confirm(comm, "*Admin*", operation) {
+        return Err(StatusWord::ConditionsNotSatisfied);
+    }
+    Ok(())
+}
+
+/// Set the management key (Yubico extension)
+///
+/// P2 is the touch policy, FF for none and FE to require touch. Command data
+/// is the key algorithm followed by the key in a '9B' tag.
+pub fn process_set_mgmt_key(comm: &mut io::Comm, state: &CardState) {
+    let touch_required = match (comm.get_p1(), comm.get_p2()) {
+        (0xFF, 0xFF) => false,
+        (0xFF, 0xFE) => true,
+        _ => return comm.reply(StatusWord::IncorrectP1P2),
+    };
+
+    if let Err(s) = parse_mgmt_key(comm) {
+        return comm.reply(s);
+    }
+
+    if let Err(s) = authorize_admin(comm, state, "Change mgmt key") {
+        return comm.reply(s);
+    }
+
+    match parse_mgmt_key(comm) {
+        Ok(key) => {
+            let record = MgmtKeyRecord {
+                key,
+                touch_required,
+            };
+            unsafe { MGMT_KEY.get_mut().update(&record) };
+            comm.reply_ok();
+        }
+        Err(s) => comm.reply(s),
+    }
+}
+
+/// Extract the new key from 'set management key' command data
+fn parse_mgmt_key(comm: &io::Comm) -> Result<[u8; MGMT_KEY_LEN], StatusWord> {
+    let data = comm.get_data().map_err(|_| StatusWord::WrongLength)?;
+
+    // Right now, only Triple DES keys are supported
+    let rest = match data {
+        [MGMT_KEY_ALGORITHM, rest @ ..] => rest,
+        [_, ..] => return Err(StatusWord::FuncNotSupported),
+        [] => return Err(StatusWord::WrongData),
+    };
+    match tlv::parse(rest) {
+        Some((key, [])) if key.tag == 0x9B => {
+            key.value.try_into().map_err(|_| StatusWord::WrongData)
+        }
+        _ => Err(StatusWord::WrongData),
+    }
+}
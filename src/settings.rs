@@ -0,0 +1,113 @@
+use crate::fascn::Fascn;
+use nanos_sdk::nvm::AtomicStorage;
+use nanos_sdk::NVMData;
+
+// Maximum length of the Key History off-card certificate URL
+// (https://nvlpubs.nist.gov/nistpubs/specialpublications/nist.sp.800-73-4.pdf, Table 19)
+pub const OFF_CARD_URL_MAX_LEN: usize = 118;
+
+/// Which PIN satisfies the PIV access control rules
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum PinPolicy {
+    // PIV Card Application PIN only
+    PivOnly,
+    // Global PIN as well, PIV Card Application PIN being the primary one
+    GlobalAllowed,
+    // Global PIN as well, and it is the primary one
+    GlobalPrimary,
+}
+
+impl PinPolicy {
+    pub fn from_u8(value: u8) -> Option<PinPolicy> {
+        match value {
+            0 => Some(Self::PivOnly),
+            1 => Some(Self::GlobalAllowed),
+            2 => Some(Self::GlobalPrimary),
+            _ => None,
+        }
+    }
+
+    /// Whether the Global PIN (key reference 00) can be verified
+    pub fn global_pin_allowed(&self) -> bool {
+        *self != Self::PivOnly
+    }
+}
+
+/// Source of the card identity, from which its serial number and UUID are
+/// derived, see `utils::card_serial`
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum SerialSource {
+    // Serial number of the device
+    Device,
+    // Seed, so that devices sharing a seed share their identity
+    Seed,
+    // Serial number set by the user
+    Fixed([u8; 4]),
+}
+
+impl SerialSource {
+    pub fn from_bytes(value: &[u8]) -> Option<SerialSource> {
+        match value {
+            [0] => Some(Self::Device),
+            [1] => Some(Self::Seed),
+            [a, b, c, d] => Some(Self::Fixed([*a, *b, *c, *d])),
+            _ => None,
+        }
+    }
+}
+
+/// Application settings, persisted in NVM
+#[derive(Copy, Clone)]
+pub struct Settings {
+    // FASC-N of the CHUID
+    pub fascn: Fascn,
+    // CHUID expiration date, as YYYYMMDD ASCII digits
+    pub chuid_expiry: [u8; 8],
+    // Number of retired keys whose certificate is only available off-card,
+    // at the URL below
+    pub off_card_certificates: u8,
+    pub off_card_url: [u8; OFF_CARD_URL_MAX_LEN],
+    pub off_card_url_len: usize,
+    pub pin_policy: PinPolicy,
+    pub serial_source: SerialSource,
+    // Firmware version reported to Yubico tools instead of the one matching
+    // the implemented features
+    pub yubico_version: Option<[u8; 3]>,
+}
+
+impl Settings {
+    const DEFAULT: Settings = Settings {
+        fascn: Fascn::DEFAULT,
+        chuid_expiry: *b"20500101",
+        off_card_certificates: 0,
+        off_card_url: [0; OFF_CARD_URL_MAX_LEN],
+        off_card_url_len: 0,
+        pin_policy: PinPolicy::PivOnly,
+        serial_source: SerialSource::Device,
+        yubico_version: None,
+    };
+}
+
+#[link_section = ".nvm_data"]
+static mut SETTINGS: NVMData<AtomicStorage<Settings>> =
+    NVMData::new(AtomicStorage::new(&Settings::DEFAULT));
+
+pub fn get() -> &'static Settings {
+    unsafe { SETTINGS.get_mut().get_ref() }
+}
+
+/// Write new settings to NVM
+pub fn set(settings: &Settings) {
+    unsafe { SETTINGS.get_mut().update(settings) };
+}
+
+/// Restore default settings
+///
+/// `AtomicStorage` writes a new value over its previous copy and keeps the
+/// current one, both copies are overwritten so that no previous setting, such
+/// as the off-card certificates URL, is left in NVM.
+pub fn reset() {
+    for _ in 0..2 {
+        unsafe { SETTINGS.get_mut().update(&Settings::DEFAULT) };
+    }
+}
@@ -0,0 +1,269 @@
+use crate::keys;
+use crate::settings;
+use core::ops::RangeInclusive;
+use nanos_sdk::nvm::AtomicStorage;
+use nanos_sdk::NVMData;
+
+// Key references of the PIV Authentication, Digital Signature, Key Management
+// and Card Authentication slots
+pub const PRIMARY_SLOTS: [u8; 4] = [0x9A, 0x9C, 0x9D, 0x9E];
+
+// Key references of the retired key management slots
+// (https://nvlpubs.nist.gov/nistpubs/specialpublications/nist.sp.800-73-4.pdf, Table 4b)
+pub const RETIRED_SLOTS: RangeInclusive<u8> = 0x82..=0x95;
+const N_RETIRED_SLOTS: usize = 20;
+
+// PIN and touch policies, as defined by Yubico
+// (https://developers.yubico.com/PIV/Introduction/Yubico_extensions.html)
+pub const PIN_POLICY_NEVER: u8 = 0x01;
+pub const PIN_POLICY_ONCE: u8 = 0x02;
+pub const PIN_POLICY_ALWAYS: u8 = 0x03;
+pub const TOUCH_POLICY_NEVER: u8 = 0x01;
+pub const TOUCH_POLICY_ALWAYS: u8 = 0x02;
+
+// Origin of a slot key, as defined by Yubico
+pub const ORIGIN_GENERATED: u8 = 0x01;
+pub const ORIGIN_IMPORTED: u8 = 0x02;
+
+/// Key held by a slot
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum SlotKey {
+    // Key derived from the seed at the derivation index of the given slot,
+    // which differs from the holding slot once the key has been moved
+    Derived(u8),
+    // Imported key encrypted with `keys::wrap_key`
+    Imported([u8; keys::PRIVATE_KEY_LEN]),
+}
+
+/// Key held by a slot, along with its PIN policy
+#[derive(Copy, Clone)]
+struct HeldKey {
+    key: SlotKey,
+    pin_policy: u8,
+}
+
+/// Key of a primary or retired slot, persisted in NVM
+#[derive(Copy, Clone)]
+enum SlotRecord {
+    // Initial content of the slot, see `factory_key`
+    Factory,
+    // Key imported or moved in the slot, None once it has been deleted
+    Key(Option<HeldKey>),
+}
+
+const N_SLOT_RECORDS: usize = PRIMARY_SLOTS.len() + N_RETIRED_SLOTS;
+
+// Record overwriting imported keys, see `reset`
+const ERASED_RECORD: SlotRecord = SlotRecord::Key(Some(HeldKey {
+    key: SlotKey::Imported([0; keys::PRIVATE_KEY_LEN]),
+    pin_policy: 0,
+}));
+
+const DEFAULT_SLOT_RECORD: NVMData<AtomicStorage<SlotRecord>> =
+    NVMData::new(AtomicStorage::new(&SlotRecord::Factory));
+
+#[link_section = ".nvm_data"]
+static mut SLOT_RECORDS: [NVMData<AtomicStorage<SlotRecord>>; N_SLOT_RECORDS] =
+    [DEFAULT_SLOT_RECORD; N_SLOT_RECORDS];
+
+/// Index of a primary or retired slot in `SLOT_RECORDS`
+fn record_index(key_ref: u8) -> Option<usize> {
+    match primary_index(key_ref) {
+        Some(index) => Some(index),
+        None if RETIRED_SLOTS.contains(&key_ref) => {
+            Some(PRIMARY_SLOTS.len() + (key_ref - RETIRED_SLOTS.start()) as usize)
+        }
+        None => None,
+    }
+}
+
+fn set_record(index: usize, record: SlotRecord) {
+    unsafe { SLOT_RECORDS[index].get_mut().update(&record) };
+}
+
+/// Key of a slot before any import, move or deletion
+fn factory_key(key_ref: u8) -> Option<SlotKey> {
+    // Primary slot and attestation keys are derived from the seed. Only the
+    // first retired slot holds a key, along with its built-in certificate.
+    if primary_index(key_ref).is_some()
+        || key_ref == keys::ATTESTATION_KEY_REF
+        || key_ref == *RETIRED_SLOTS.start()
+    {
+        Some(SlotKey::Derived(key_ref))
+    } else {
+        None
+    }
+}
+
+/// PIN policy of an imported key, when the host leaves it to its default
+///
+/// These are the YubiKey defaults: Digital Signature keys require the PIN
+/// for each use, Card Authentication keys never require it and other keys
+/// require it once per session.
+pub fn default_pin_policy(key_ref: u8) -> u8 {
+    match key_ref {
+        0x9C => PIN_POLICY_ALWAYS,
+        0x9E | keys::ATTESTATION_KEY_REF => PIN_POLICY_NEVER,
+        _ => PIN_POLICY_ONCE,
+    }
+}
+
+fn held_key(key_ref: u8) -> Option<HeldKey> {
+    let record = match record_index(key_ref) {
+        Some(index) => unsafe { *SLOT_RECORDS[index].get_mut().get_ref() },
+        None => SlotRecord::Factory,
+    };
+    match record {
+        SlotRecord::Factory => {
+            // The built-in certificate of the first retired slot declares
+            // that its key never requires the PIN
+            let pin_policy = if key_ref == *RETIRED_SLOTS.start() {
+                PIN_POLICY_NEVER
+            } else {
+                default_pin_policy(key_ref)
+            };
+            factory_key(key_ref).map(|key| HeldKey { key, pin_policy })
+        }
+        SlotRecord::Key(held) => held,
+    }
+}
+
+/// Key held by a slot, None if the slot is empty
+pub fn slot_key(key_ref: u8) -> Option<SlotKey> {
+    held_key(key_ref).map(|held| held.key)
+}
+
+/// PIN policy of the key of a populated slot
+pub fn pin_policy(key_ref: u8) -> u8 {
+    held_key(key_ref).map_or(PIN_POLICY_NEVER, |held| held.pin_policy)
+}
+
+/// Whether a slot still holds its initial key, see `factory_key`
+pub fn has_factory_key(key_ref: u8) -> bool {
+    let key = slot_key(key_ref);
+    key.is_some() && key == factory_key(key_ref)
+}
+
+/// Whether a slot key can be replaced, by importing or moving a key, or
+/// deleted
+pub fn can_replace(key_ref: u8) -> bool {
+    record_index(key_ref).is_some()
+}
+
+/// Whether a key can be imported or moved in a slot
+///
+/// Retired slots are populated in order, starting with the first one, as the
+/// Key History object only gives their number: the key of a populated retired
+/// slot can be replaced, and the next retired slot filled unless the remaining
+/// ones are taken by keys with an off-card certificate.
+pub fn can_fill(key_ref: u8) -> bool {
+    if !RETIRED_SLOTS.contains(&key_ref) {
+        return can_replace(key_ref);
+    }
+    let next = next_retired_slot();
+    let off_card = settings::get().off_card_certificates;
+    is_populated(key_ref)
+        || key_ref == next
+            && (next - RETIRED_SLOTS.start()) as usize + 1 + off_card as usize <= N_RETIRED_SLOTS
+}
+
+/// Whether the key of a slot can be moved away or deleted
+///
+/// Only the last populated retired slot can be emptied, see `can_fill`.
+pub fn can_empty(key_ref: u8) -> bool {
+    if !RETIRED_SLOTS.contains(&key_ref) {
+        return can_replace(key_ref);
+    }
+    key_ref + 1 == next_retired_slot()
+}
+
+/// Retired slot following the last populated one
+fn next_retired_slot() -> u8 {
+    RETIRED_SLOTS
+        .rev()
+        .find(|k| is_populated(*k))
+        .map_or(*RETIRED_SLOTS.start(), |k| k + 1)
+}
+
+/// Replace the key of a primary or retired slot
+pub fn import_key(key_ref: u8, raw_key: &[u8; keys::PRIVATE_KEY_LEN], pin_policy: u8) {
+    if let Some(index) = record_index(key_ref) {
+        let key = SlotKey::Imported(keys::wrap_key(key_ref, raw_key));
+        set_record(index, SlotRecord::Key(Some(HeldKey { key, pin_policy })));
+    }
+}
+
+/// Move the key of a slot to another, empty, slot
+///
+/// Derived keys keep their derivation index and imported keys are encrypted
+/// again for their new slot, both keep their PIN policy. The key is written
+/// to its new slot before being removed from the previous one, so that it is
+/// never lost.
+pub fn move_key(from: u8, to: u8) {
+    let (from_index, to_index) = match (record_index(from), record_index(to)) {
+        (Some(from_index), Some(to_index)) => (from_index, to_index),
+        _ => return,
+    };
+    let mut held = match held_key(from) {
+        Some(held) => held,
+        None => return,
+    };
+    if let SlotKey::Imported(wrapped_key) = held.key {
+        let raw_key = keys::unwrap_key(from, &wrapped_key);
+        held.key = SlotKey::Imported(keys::wrap_key(to, &raw_key));
+    }
+    set_record(to_index, SlotRecord::Key(Some(held)));
+    set_record(from_index, SlotRecord::Key(None));
+}
+
+/// Delete the key of a slot, which becomes empty
+pub fn delete_key(key_ref: u8) {
+    if let Some(index) = record_index(key_ref) {
+        set_record(index, SlotRecord::Key(None));
+    }
+}
+
+/// Restore the initial keys of all the slots, erasing imported keys
+///
+/// `AtomicStorage` writes a new value over its previous copy and keeps the
+/// current one: both copies are overwritten with a blank key first, as the
+/// initial record does not overwrite the key bytes.
+pub fn reset() {
+    for index in 0..N_SLOT_RECORDS {
+        set_record(index, ERASED_RECORD);
+        set_record(index, ERASED_RECORD);
+        set_record(index, SlotRecord::Factory);
+    }
+}
+
+/// Origin of the key of a populated slot
+pub fn origin(key_ref: u8) -> u8 {
+    match slot_key(key_ref) {
+        Some(SlotKey::Imported(_)) => ORIGIN_IMPORTED,
+        _ => ORIGIN_GENERATED,
+    }
+}
+
+/// Key reference of the retired slot whose certificate is the retired
+/// certificate object `index`
+pub fn retired_key_ref(index: u8) -> u8 {
+    RETIRED_SLOTS.start() + index
+}
+
+/// Index of a primary slot in `PRIMARY_SLOTS`
+pub fn primary_index(key_ref: u8) -> Option<usize> {
+    PRIMARY_SLOTS.iter().position(|k| *k == key_ref)
+}
+
+/// Whether a slot holds a key
+pub fn is_populated(key_ref: u8) -> bool {
+    slot_key(key_ref).is_some()
+}
+
+/// Number of retired slots holding a key
+///
+/// The specification requires retired slots to be populated in order,
+/// starting with the first one, see `can_fill`.
+pub fn retired_on_card() -> u8 {
+    RETIRED_SLOTS.filter(|k| is_populated(*k)).count() as u8
+}
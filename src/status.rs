@@ -10,14 +10,16 @@ pub enum StatusWord {
     FuncNotSupported,
     FileNotFound,
     IncorrectP1P2,
-    VerificationFailed,
+    // Number of remaining attempts
+    VerificationFailed(u8),
+    ConditionsNotSatisfied,
+    SecurityStatusNotSatisfied,
+    RefDataNotFound,
+    FileFull,
     // SecureMessagingNotSupported = 0x6882,
-    // SecurityStatusNotSatisfied = 0x6982,
     // AuthMethodBlocked = 0x6983,
     // MissingSecureMessagingData = 0x6987,
     // IncorrectSecureMessagingData = 0x6988,
-    // FileFull = 0x6A84,
-    // RefDataNotFound = 0x6A88,
 }
 
 impl From<StatusWord> for u16 {
@@ -29,7 +31,11 @@ impl From<StatusWord> for u16 {
             StatusWord::FuncNotSupported => 0x6A81,
             StatusWord::FileNotFound => 0x6A82,
             StatusWord::IncorrectP1P2 => 0x6A86,
-            StatusWord::VerificationFailed => 0x6300,
+            StatusWord::VerificationFailed(retries) => 0x63C0 + (retries.min(0xF) as u16),
+            StatusWord::ConditionsNotSatisfied => 0x6985,
+            StatusWord::SecurityStatusNotSatisfied => 0x6982,
+            StatusWord::RefDataNotFound => 0x6A88,
+            StatusWord::FileFull => 0x6A84,
         }
     }
 }
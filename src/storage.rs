@@ -0,0 +1,315 @@
+use crate::status::*;
+use core::ffi::c_void;
+use core::mem::size_of;
+use nanos_sdk::bindings::nvm_write;
+use nanos_sdk::NVMData;
+
+// Maximum content length of the data objects stored in NVM
+// (https://nvlpubs.nist.gov/nistpubs/specialpublications/nist.sp.800-73-4.pdf, Appendix A)
+#[cfg(not(target_os = "nanos"))]
+const CERTIFICATE_MAX_LEN: usize = 1905;
+#[cfg(not(target_os = "nanos"))]
+const FINGERPRINTS_MAX_LEN: usize = 4006;
+#[cfg(not(target_os = "nanos"))]
+const FACIAL_IMAGE_MAX_LEN: usize = 12710;
+// Printed Information fields, see `data_object::check_printed_information`
+pub const PRINTED_INFORMATION_MAX_LEN: usize = 256;
+
+// The Nano S flash cannot hold the app along with objects of the maximum
+// lengths, shorter ones are stored
+#[cfg(target_os = "nanos")]
+const CERTIFICATE_MAX_LEN: usize = 1024;
+#[cfg(target_os = "nanos")]
+const FINGERPRINTS_MAX_LEN: usize = 1024;
+#[cfg(target_os = "nanos")]
+const FACIAL_IMAGE_MAX_LEN: usize = 2048;
+
+// Capacity of the store of objects unknown to the card, see `start_blob`
+#[cfg(not(target_os = "nanos"))]
+const BLOB_STORE_SIZE: usize = 4096;
+#[cfg(target_os = "nanos")]
+const BLOB_STORE_SIZE: usize = 1024;
+// Each blob is stored after its 3 bytes tag and 2 bytes length
+const BLOB_HEADER_LEN: usize = 5;
+
+/// Data objects written by the host and persisted in NVM
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum StoredObject {
+    // Index of the primary slot, see `slots::PRIMARY_SLOTS`
+    Certificate(usize),
+    // Index of the retired slot, see `slots::RETIRED_SLOTS`
+    RetiredCertificate(usize),
+    Fingerprints,
+    FacialImage,
+    PrintedInformation,
+}
+
+/// Variable length data persisted in NVM
+pub struct NvmObject<const N: usize> {
+    len: usize,
+    data: [u8; N],
+}
+
+impl<const N: usize> NvmObject<N> {
+    pub const fn new() -> NvmObject<N> {
+        Self {
+            len: 0,
+            data: [0; N],
+        }
+    }
+
+    fn raw(&mut self) -> RawNvmObject<'_> {
+        RawNvmObject {
+            len: &mut self.len,
+            data: &mut self.data,
+        }
+    }
+}
+
+/// `NvmObject` with its capacity erased
+struct RawNvmObject<'a> {
+    len: &'a mut usize,
+    data: &'a mut [u8],
+}
+
+impl RawNvmObject<'static> {
+    fn get(self) -> Option<&'static [u8]> {
+        match *self.len {
+            0 => None,
+            len => Some(&self.data[..len]),
+        }
+    }
+}
+
+impl RawNvmObject<'_> {
+    fn write_len(&mut self, len: usize) {
+        let mut len = len;
+        unsafe {
+            nvm_write(
+                self.len as *mut usize as *mut c_void,
+                &mut len as *mut usize as *mut c_void,
+                size_of::<usize>() as u32,
+            );
+        }
+    }
+
+    fn write_data(&mut self, offset: usize, chunk: &[u8]) {
+        unsafe {
+            nvm_write(
+                self.data[offset..].as_mut_ptr() as *mut c_void,
+                chunk.as_ptr() as *mut c_void,
+                chunk.len() as u32,
+            );
+        }
+    }
+
+    /// Erase the object, overwriting all its data area rather than only
+    /// the part in use, which may not cover what was stored before
+    fn erase(&mut self) {
+        self.write_len(0);
+        let zeros = [0u8; 256];
+        let mut offset = 0;
+        while offset < self.data.len() {
+            let n = zeros.len().min(self.data.len() - offset);
+            self.write_data(offset, &zeros[..n]);
+            offset += n;
+        }
+    }
+}
+
+const NO_CERTIFICATE: NVMData<NvmObject<CERTIFICATE_MAX_LEN>> = NVMData::new(NvmObject::new());
+
+// Certificate objects of the primary slots
+const N_CERTIFICATES: usize = 4;
+#[link_section = ".nvm_data"]
+static mut CERTIFICATES: [NVMData<NvmObject<CERTIFICATE_MAX_LEN>>; N_CERTIFICATES] =
+    [NO_CERTIFICATE; N_CERTIFICATES];
+
+// Certificate objects of the retired slots
+const N_RETIRED_CERTIFICATES: usize = 20;
+#[link_section = ".nvm_data"]
+static mut RETIRED_CERTIFICATES: [NVMData<NvmObject<CERTIFICATE_MAX_LEN>>; N_RETIRED_CERTIFICATES] =
+    [NO_CERTIFICATE; N_RETIRED_CERTIFICATES];
+
+#[link_section = ".nvm_data"]
+static mut FINGERPRINTS: NVMData<NvmObject<FINGERPRINTS_MAX_LEN>> = NVMData::new(NvmObject::new());
+
+#[link_section = ".nvm_data"]
+static mut FACIAL_IMAGE: NVMData<NvmObject<FACIAL_IMAGE_MAX_LEN>> = NVMData::new(NvmObject::new());
+
+#[link_section = ".nvm_data"]
+static mut PRINTED_INFORMATION: NVMData<NvmObject<PRINTED_INFORMATION_MAX_LEN>> =
+    NVMData::new(NvmObject::new());
+
+// Objects unknown to the card, stored one after the other with their tag
+#[link_section = ".nvm_data"]
+static mut BLOBS: NVMData<NvmObject<BLOB_STORE_SIZE>> = NVMData::new(NvmObject::new());
+
+/// NVM area written by a `Writer`
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Area {
+    Object(StoredObject),
+    Blobs,
+}
+
+fn nvm_object(area: Area) -> RawNvmObject<'static> {
+    unsafe {
+        match area {
+            Area::Object(StoredObject::Certificate(index)) => CERTIFICATES[index].get_mut().raw(),
+            Area::Object(StoredObject::RetiredCertificate(index)) => {
+                RETIRED_CERTIFICATES[index].get_mut().raw()
+            }
+            Area::Object(StoredObject::Fingerprints) => FINGERPRINTS.get_mut().raw(),
+            Area::Object(StoredObject::FacialImage) => FACIAL_IMAGE.get_mut().raw(),
+            Area::Object(StoredObject::PrintedInformation) => PRINTED_INFORMATION.get_mut().raw(),
+            Area::Blobs => BLOBS.get_mut().raw(),
+        }
+    }
+}
+
+/// Stored content, None if nothing has been stored
+pub fn get(object: StoredObject) -> Option<&'static [u8]> {
+    nvm_object(Area::Object(object)).get()
+}
+
+/// Erase all the stored objects, including blobs, overwriting their data
+pub fn erase_all() {
+    for index in 0..N_CERTIFICATES {
+        nvm_object(Area::Object(StoredObject::Certificate(index))).erase();
+    }
+    for index in 0..N_RETIRED_CERTIFICATES {
+        nvm_object(Area::Object(StoredObject::RetiredCertificate(index))).erase();
+    }
+    nvm_object(Area::Object(StoredObject::Fingerprints)).erase();
+    nvm_object(Area::Object(StoredObject::FacialImage)).erase();
+    nvm_object(Area::Object(StoredObject::PrintedInformation)).erase();
+    nvm_object(Area::Blobs).erase();
+}
+
+/// Find the blob stored with `tag`, returning its offset in the store along
+/// with its value
+fn find_blob<'a>(blobs: &'a [u8], tag: &[u8; 3]) -> Option<(usize, &'a [u8])> {
+    let mut offset = 0;
+    while offset + BLOB_HEADER_LEN <= blobs.len() {
+        let header = &blobs[offset..offset + BLOB_HEADER_LEN];
+        let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+        let end = (offset + BLOB_HEADER_LEN + len).min(blobs.len());
+        if header[..3] == tag[..] {
+            return Some((offset, &blobs[offset + BLOB_HEADER_LEN..end]));
+        }
+        offset = end;
+    }
+    None
+}
+
+/// Value of an object unknown to the card, as written with `start_blob`
+pub fn get_blob(tag: &[u8; 3]) -> Option<&'static [u8]> {
+    let blobs = nvm_object(Area::Blobs).get()?;
+    find_blob(blobs, tag).map(|(_, value)| value)
+}
+
+/// Remove a blob from the store, moving the following ones over it
+///
+/// Blobs following the removed one are dropped if the removal is
+/// interrupted, the store is never left corrupted.
+fn remove_blob(tag: &[u8; 3]) {
+    let mut raw = nvm_object(Area::Blobs);
+    let used = *raw.len;
+    let (offset, size) = match find_blob(&raw.data[..used], tag) {
+        Some((offset, value)) => (offset, BLOB_HEADER_LEN + value.len()),
+        None => return,
+    };
+
+    raw.write_len(offset);
+    let mut chunk = [0u8; 64];
+    let mut src = offset + size;
+    while src < used {
+        let n = chunk.len().min(used - src);
+        chunk[..n].copy_from_slice(&raw.data[src..src + n]);
+        raw.write_data(src - size, &chunk[..n]);
+        src += n;
+    }
+    raw.write_len(used - size);
+}
+
+/// Write of an object content, which may be received in several chunks
+///
+/// The stored length is cleared when the write starts and only set once
+/// all the content is written, so that an interrupted write leaves the
+/// object empty rather than corrupted.
+pub struct Writer {
+    area: Area,
+    // Offset of the content in the area
+    start: usize,
+    len: usize,
+    written: usize,
+}
+
+impl Writer {
+    /// Start writing `len` bytes of content, an empty content erases the
+    /// object
+    pub fn start(object: StoredObject, len: usize) -> Result<Writer, StatusWord> {
+        let mut raw = nvm_object(Area::Object(object));
+        if len > raw.data.len() {
+            return Err(StatusWord::WrongLength);
+        }
+        raw.write_len(0);
+        Ok(Self {
+            area: Area::Object(object),
+            start: 0,
+            len,
+            written: 0,
+        })
+    }
+
+    /// Start writing the `len` bytes value of an object unknown to the card,
+    /// replacing the previous one. An empty value erases the object.
+    ///
+    /// All these objects share the capacity of the store, `FileFull` is
+    /// returned once it is exhausted.
+    pub fn start_blob(tag: &[u8; 3], len: usize) -> Result<Writer, StatusWord> {
+        let raw = nvm_object(Area::Blobs);
+        let used = *raw.len;
+        let freed = match find_blob(&raw.data[..used], tag) {
+            Some((_, value)) => BLOB_HEADER_LEN + value.len(),
+            None => 0,
+        };
+        if len != 0 && used - freed + BLOB_HEADER_LEN + len > BLOB_STORE_SIZE {
+            return Err(StatusWord::FileFull);
+        }
+
+        remove_blob(tag);
+        let mut raw = nvm_object(Area::Blobs);
+        let used = *raw.len;
+        if len != 0 {
+            let mut header = [0u8; BLOB_HEADER_LEN];
+            header[..3].copy_from_slice(tag);
+            header[3..].copy_from_slice(&(len as u16).to_be_bytes());
+            raw.write_data(used, &header);
+        }
+        Ok(Self {
+            area: Area::Blobs,
+            start: used + BLOB_HEADER_LEN,
+            len,
+            written: 0,
+        })
+    }
+
+    pub fn append(&mut self, chunk: &[u8]) -> Result<(), StatusWord> {
+        if chunk.len() > self.len - self.written {
+            return Err(StatusWord::WrongLength);
+        }
+
+        let mut raw = nvm_object(self.area);
+        raw.write_data(self.start + self.written, chunk);
+        self.written += chunk.len();
+        if self.is_complete() && self.len != 0 {
+            raw.write_len(self.start + self.len);
+        }
+        Ok(())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.written == self.len
+    }
+}
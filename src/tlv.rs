@@ -0,0 +1,71 @@
+/// BER-TLV element found in command data
+pub struct Tlv<'a> {
+    pub tag: u16,
+    pub value: &'a [u8],
+    // Whole encoded element, including tag and length
+    pub raw: &'a [u8],
+}
+
+/// Parse the first BER-TLV element of `data`.
+///
+/// Returns the element and the bytes following it, or None if `data` does
+/// not start with a well-formed element.
+/// Only tags up to two bytes and lengths up to 0xFFFF are supported.
+pub fn parse(data: &[u8]) -> Option<(Tlv<'_>, &[u8])> {
+    let (tag, len, offset) = parse_header(data)?;
+
+    let end = offset.checked_add(len)?;
+    if end > data.len() {
+        return None;
+    }
+
+    Some((
+        Tlv {
+            tag,
+            value: &data[offset..end],
+            raw: &data[..end],
+        },
+        &data[end..],
+    ))
+}
+
+/// Parse the tag and length of the first BER-TLV element of `data`, whose
+/// value may not be complete.
+///
+/// Returns the tag, the length of the value and the length of the header.
+pub fn parse_header(data: &[u8]) -> Option<(u16, usize, usize)> {
+    let mut offset = 0;
+
+    // Tag
+    let first = *data.first()?;
+    let tag = if first & 0x1F == 0x1F {
+        let second = *data.get(1)?;
+        if second & 0x80 != 0 {
+            return None;
+        }
+        offset += 2;
+        u16::from_be_bytes([first, second])
+    } else {
+        offset += 1;
+        first as u16
+    };
+
+    // Length
+    let len = match *data.get(offset)? {
+        l if l < 0x80 => {
+            offset += 1;
+            l as usize
+        }
+        0x81 => {
+            offset += 2;
+            *data.get(offset - 1)? as usize
+        }
+        0x82 => {
+            offset += 3;
+            u16::from_be_bytes([*data.get(offset - 2)?, *data.get(offset - 1)?]) as usize
+        }
+        _ => return None,
+    };
+
+    Some((tag, len, offset))
+}
@@ -0,0 +1,56 @@
+use crate::bitmaps;
+use crate::layout::*;
+use crate::screen_util;
+use crate::utils;
+use nanos_sdk::buttons::ButtonEvent;
+use nanos_sdk::io;
+
+/// Screen shown while waiting for commands
+pub fn ready_screen() {
+    bitmaps::PADLOCK.draw(64 - (bitmaps::PADLOCK.width as i32) / 2, 4);
+    "*PIV* ready".display(Line::Second, Layout::Centered);
+}
+
+/// Show a message on two lines, until the user moves on with the right
+/// button
+pub fn notice(comm: &mut io::Comm, first: &str, second: &str) {
+    screen_util::fulldraw(0, 0, &bitmaps::BLANK);
+    first.display(Line::First, Layout::Centered);
+    second.display(Line::Second, Layout::Centered);
+    "Next".display(Line::Third, Layout::RightAligned);
+
+    while !matches!(
+        comm.next_event(),
+        io::Event::Button(ButtonEvent::RightButtonRelease)
+    ) {}
+}
+
+/// Ask the user to approve an operation on the device.
+///
+/// Left button rejects, right button accepts.
+pub fn confirm(comm: &mut io::Comm, title: &str, detail: &str) -> bool {
+    screen_util::fulldraw(0, 0, &bitmaps::BLANK);
+    title.display(Line::First, Layout::Centered);
+    detail.display(Line::Second, Layout::Centered);
+    "Reject".display(Line::Third, Layout::LeftAligned);
+    "Accept".display(Line::Third, Layout::RightAligned);
+
+    let approved = loop {
+        match comm.next_event() {
+            io::Event::Button(ButtonEvent::LeftButtonRelease) => break false,
+            io::Event::Button(ButtonEvent::RightButtonRelease) => break true,
+            _ => (),
+        }
+    };
+
+    screen_util::fulldraw(0, 0, &bitmaps::BLANK);
+    ready_screen();
+    approved
+}
+
+/// Ask the user to approve an operation with the key of a slot
+pub fn confirm_slot(comm: &mut io::Comm, title: &str, key_ref: u8) -> bool {
+    let mut label = *b"Slot 00";
+    label[5..].copy_from_slice(&utils::hex_byte(key_ref));
+    confirm(comm, title, core::str::from_utf8(&label).unwrap())
+}
@@ -1,9 +1,12 @@
+use crate::keys;
+use crate::settings::{self, SerialSource};
 use nanos_sdk::bindings::os_serial;
+use tinyvec::ArrayVec;
 use uuid::Uuid;
 
 const LEDGER_SERIAL_SIZE: usize = 7;
 
-pub fn device_serial() -> [u8; LEDGER_SERIAL_SIZE] {
+fn device_serial() -> [u8; LEDGER_SERIAL_SIZE] {
     let mut serial = [0_u8; LEDGER_SERIAL_SIZE];
 
     unsafe {
@@ -13,8 +16,67 @@ pub fn device_serial() -> [u8; LEDGER_SERIAL_SIZE] {
     serial
 }
 
-pub fn device_uuid() -> [u8; 16] {
-    let serial = device_serial();
+/// Unique identifier of the card, as selected by the serial source setting
+fn card_identity() -> ArrayVec<[u8; 32]> {
+    let mut identity = ArrayVec::new();
+    match settings::get().serial_source {
+        SerialSource::Device => identity.extend_from_slice(&device_serial()),
+        SerialSource::Seed => identity.extend_from_slice(&keys::seed_identifier()),
+        SerialSource::Fixed(serial) => identity.extend_from_slice(&serial),
+    }
+    identity
+}
+
+/// Card serial number, as reported to Yubico tools and in attestation
+/// certificates
+///
+/// Unless it is set by the user, it is the beginning of the SHA-256 digest of
+/// the card identity, which is stable but not unique.
+pub fn card_serial() -> [u8; 4] {
+    match settings::get().serial_source {
+        SerialSource::Fixed(serial) => serial,
+        _ => {
+            let digest = keys::sha256(&card_identity());
+            [digest[0], digest[1], digest[2], digest[3]]
+        }
+    }
+}
+
+/// Card UUID, identifying the card in the CHUID and the CCC
+pub fn card_uuid() -> [u8; 16] {
     let custom_namespace = Uuid::new_v5(&Uuid::NAMESPACE_DNS, b"ledger.fr");
-    Uuid::new_v5(&custom_namespace, &serial).into_bytes()
+    Uuid::new_v5(&custom_namespace, &card_identity()).into_bytes()
+}
+
+/// Uppercase hexadecimal representation of a byte
+pub fn hex_byte(b: u8) -> [u8; 2] {
+    const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+    [DIGITS[(b >> 4) as usize], DIGITS[(b & 0xF) as usize]]
+}
+
+/// Parse a YYYYMMDD date made of ASCII digits
+pub fn parse_date(value: &[u8]) -> Option<[u8; 8]> {
+    let date: [u8; 8] = value.try_into().ok()?;
+    if !date.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+
+    let digit = |i: usize| (date[i] - b'0') as u16;
+    let year = digit(0) * 1000 + digit(1) * 100 + digit(2) * 10 + digit(3);
+    let month = digit(4) * 10 + digit(5);
+    let day = digit(6) * 10 + digit(7);
+
+    let leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap_year => 29,
+        2 => 28,
+        _ => return None,
+    };
+    if !(1..=days_in_month).contains(&day) {
+        return None;
+    }
+
+    Some(date)
 }
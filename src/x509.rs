@@ -0,0 +1,308 @@
+use crate::der::*;
+use crate::keys;
+use crate::utils::{card_uuid, hex_byte};
+use nanos_sdk::bindings::cx_ecfp_private_key_t;
+use tinyvec::{Array, ArrayVec};
+
+// id-ecPublicKey (1.2.840.10045.2.1)
+const OID_EC_PUBLIC_KEY: [u8; 7] = [0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01];
+// prime256v1 (1.2.840.10045.3.1.7)
+const OID_PRIME256V1: [u8; 8] = [0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07];
+// ecdsa-with-SHA256 (1.2.840.10045.4.3.2)
+pub const OID_ECDSA_WITH_SHA256: [u8; 8] = [0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02];
+// commonName (2.5.4.3)
+const OID_COMMON_NAME: [u8; 3] = [0x55, 0x04, 0x03];
+// keyUsage (2.5.29.15)
+const OID_KEY_USAGE: [u8; 3] = [0x55, 0x1D, 0x0F];
+// extKeyUsage (2.5.29.37)
+const OID_EXT_KEY_USAGE: [u8; 3] = [0x55, 0x1D, 0x25];
+// id-PIV-content-signing (2.16.840.1.101.3.6.7)
+const OID_PIV_CONTENT_SIGNING: [u8; 8] = [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x06, 0x07];
+// basicConstraints (2.5.29.19)
+const OID_BASIC_CONSTRAINTS: [u8; 3] = [0x55, 0x1D, 0x13];
+// Yubico attestation extensions (1.3.6.1.4.1.41482.3.x): firmware version,
+// serial number, PIN and touch policies, form factor
+const OID_YUBICO_FIRMWARE: [u8; 10] = [0x2B, 0x06, 0x01, 0x04, 0x01, 0x82, 0xC4, 0x0A, 0x03, 0x03];
+const OID_YUBICO_SERIAL: [u8; 10] = [0x2B, 0x06, 0x01, 0x04, 0x01, 0x82, 0xC4, 0x0A, 0x03, 0x07];
+const OID_YUBICO_POLICY: [u8; 10] = [0x2B, 0x06, 0x01, 0x04, 0x01, 0x82, 0xC4, 0x0A, 0x03, 0x08];
+const OID_YUBICO_FORM_FACTOR: [u8; 10] =
+    [0x2B, 0x06, 0x01, 0x04, 0x01, 0x82, 0xC4, 0x0A, 0x03, 0x09];
+// PKCS#9 extensionRequest (1.2.840.113549.1.9.14)
+const OID_EXTENSION_REQUEST: [u8; 9] = [0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x0E];
+
+// Validity period of generated certificates
+const NOT_BEFORE: &[u8; 13] = b"220101000000Z";
+const NOT_AFTER: &[u8; 15] = b"99991231235959Z";
+
+const CONTENT_SIGNER_NAME: &str = "Ledger PIV Content Signer";
+const ATTESTATION_NAME: &str = "Ledger PIV Attestation";
+
+// Unspecified form factor, as defined by Yubico
+const FORM_FACTOR_UNSPECIFIED: u8 = 0x00;
+
+/// Name made of a single common name attribute
+pub fn write_name<A: Array<Item = u8>>(w: &mut DerWriter<A>, common_name: &str) {
+    let name = w.open(TAG_SEQUENCE);
+    let rdn = w.open(TAG_SET);
+    let attribute = w.open(TAG_SEQUENCE);
+    w.write(TAG_OID, &OID_COMMON_NAME);
+    w.write(TAG_UTF8_STRING, common_name.as_bytes());
+    w.close(attribute);
+    w.close(rdn);
+    w.close(name);
+}
+
+/// Certificate extension, to be written in the `write_certificate` callback
+pub fn write_extension<A: Array<Item = u8>>(
+    w: &mut DerWriter<A>,
+    oid: &[u8],
+    critical: bool,
+    value: &[u8],
+) {
+    let extension = w.open(TAG_SEQUENCE);
+    w.write(TAG_OID, oid);
+    if critical {
+        w.write(0x01, &[0xFF]);
+    }
+    w.write(TAG_OCTET_STRING, value);
+    w.close(extension);
+}
+
+/// SubjectPublicKeyInfo of a Secp256r1 public key
+pub fn write_public_key_info<A: Array<Item = u8>>(
+    w: &mut DerWriter<A>,
+    public_key: &[u8; keys::PUBLIC_KEY_LEN],
+) {
+    let spki = w.open(TAG_SEQUENCE);
+    let alg = w.open(TAG_SEQUENCE);
+    w.write(TAG_OID, &OID_EC_PUBLIC_KEY);
+    w.write(TAG_OID, &OID_PRIME256V1);
+    w.close(alg);
+    w.bit_string(public_key);
+    w.close(spki);
+}
+
+/// Sign everything written since `tbs_start` and append the signature
+/// algorithm and value, as found at the end of certificates and CSR
+pub fn write_signature<A: Array<Item = u8>>(
+    w: &mut DerWriter<A>,
+    pvkey: &cx_ecfp_private_key_t,
+    tbs_start: usize,
+) {
+    let signature = keys::sign_sha256(pvkey, w.slice_from(tbs_start));
+
+    let alg = w.open(TAG_SEQUENCE);
+    w.write(TAG_OID, &OID_ECDSA_WITH_SHA256);
+    w.close(alg);
+    w.bit_string(&signature);
+}
+
+/// PKCS#10 Certificate Signing Request (RFC 2986)
+///
+/// `subject` is a DER encoded Name and `extensions` DER encoded Extensions,
+/// requested through the extensionRequest attribute.
+pub fn write_csr<A: Array<Item = u8>>(
+    w: &mut DerWriter<A>,
+    pvkey: &cx_ecfp_private_key_t,
+    subject: &[u8],
+    extensions: Option<&[u8]>,
+) {
+    let csr = w.open(TAG_SEQUENCE);
+
+    let info_start = w.len();
+    let info = w.open(TAG_SEQUENCE);
+    w.integer(&[0]);
+    w.raw(subject);
+    write_public_key_info(w, &keys::public_key(pvkey));
+    let attributes = w.open(0xA0);
+    if let Some(extensions) = extensions {
+        let attribute = w.open(TAG_SEQUENCE);
+        w.write(TAG_OID, &OID_EXTENSION_REQUEST);
+        let values = w.open(TAG_SET);
+        w.raw(extensions);
+        w.close(values);
+        w.close(attribute);
+    }
+    w.close(attributes);
+    w.close(info);
+
+    write_signature(w, pvkey, info_start);
+    w.close(csr);
+}
+
+/// X.509 v3 certificate signed with `signer`
+///
+/// `extensions` writes the certificate extensions, see `write_extension`.
+pub fn write_certificate<A: Array<Item = u8>>(
+    w: &mut DerWriter<A>,
+    signer: &cx_ecfp_private_key_t,
+    serial: &[u8],
+    issuer: &str,
+    subject: &str,
+    public_key: &[u8; keys::PUBLIC_KEY_LEN],
+    extensions: impl FnOnce(&mut DerWriter<A>),
+) {
+    let certificate = w.open(TAG_SEQUENCE);
+
+    let tbs_start = w.len();
+    let tbs = w.open(TAG_SEQUENCE);
+    let version = w.open(0xA0);
+    w.integer(&[2]);
+    w.close(version);
+    w.integer(serial);
+    let alg = w.open(TAG_SEQUENCE);
+    w.write(TAG_OID, &OID_ECDSA_WITH_SHA256);
+    w.close(alg);
+    write_name(w, issuer);
+    let validity = w.open(TAG_SEQUENCE);
+    w.write(TAG_UTC_TIME, NOT_BEFORE);
+    w.write(TAG_GENERALIZED_TIME, NOT_AFTER);
+    w.close(validity);
+    write_name(w, subject);
+    write_public_key_info(w, public_key);
+    let explicit = w.open(0xA3);
+    let list = w.open(TAG_SEQUENCE);
+    extensions(w);
+    w.close(list);
+    w.close(explicit);
+    w.close(tbs);
+
+    write_signature(w, signer, tbs_start);
+    w.close(certificate);
+}
+
+/// Serial number of the content signer certificate
+pub fn content_signer_serial() -> [u8; 16] {
+    card_uuid()
+}
+
+/// Certificate of a slot key, issued by the content signer
+///
+/// Served for slots in which the host did not store a certificate.
+pub fn write_slot_certificate<A: Array<Item = u8>>(w: &mut DerWriter<A>, key_ref: u8) {
+    let mut subject = *b"Ledger PIV Slot 00";
+    subject[16..].copy_from_slice(&hex_byte(key_ref));
+    let mut serial = [0u8; 17];
+    serial[..16].copy_from_slice(&content_signer_serial());
+    serial[16] = key_ref;
+
+    // PIV Authentication and Card Authentication keys authenticate the
+    // cardholder or the card, Digital Signature keys also provide
+    // non-repudiation and Key Management keys, retired ones included, are
+    // used for key agreement
+    let key_usage: &[u8] = match key_ref {
+        0x9C => &[0x03, 0x02, 0x06, 0xC0],
+        0x9D | 0x82..=0x95 => &[0x03, 0x02, 0x03, 0x08],
+        _ => &[0x03, 0x02, 0x07, 0x80],
+    };
+
+    write_certificate(
+        w,
+        &keys::content_signer_private_key(),
+        &serial,
+        CONTENT_SIGNER_NAME,
+        core::str::from_utf8(&subject).unwrap(),
+        &keys::public_key(&keys::slot_private_key(key_ref)),
+        |w| write_extension(w, &OID_KEY_USAGE, true, key_usage),
+    );
+}
+
+/// Self-signed certificate of the attestation key, the CA issuing
+/// attestation certificates
+pub fn write_attestation_ca_certificate<A: Array<Item = u8>>(w: &mut DerWriter<A>) {
+    let pvkey = keys::slot_private_key(keys::ATTESTATION_KEY_REF);
+    let mut serial = [0u8; 17];
+    serial[..16].copy_from_slice(&card_uuid());
+    serial[16] = keys::ATTESTATION_KEY_REF;
+
+    write_certificate(
+        w,
+        &pvkey,
+        &serial,
+        ATTESTATION_NAME,
+        ATTESTATION_NAME,
+        &keys::public_key(&pvkey),
+        |w| {
+            // CA, keyCertSign
+            write_extension(
+                w,
+                &OID_BASIC_CONSTRAINTS,
+                true,
+                &[0x30, 0x03, 0x01, 0x01, 0xFF],
+            );
+            write_extension(w, &OID_KEY_USAGE, true, &[0x03, 0x02, 0x02, 0x04]);
+        },
+    );
+}
+
+/// Attestation of a slot key, issued by the attestation key
+///
+/// It carries the Yubico extensions describing the card and the key
+/// policies, `serial` being the card serial number.
+pub fn write_attestation_certificate<A: Array<Item = u8>>(
+    w: &mut DerWriter<A>,
+    key_ref: u8,
+    serial: &[u8; 4],
+    version: &[u8; 3],
+    policy: &[u8; 2],
+) {
+    let mut subject = *b"Ledger PIV Attestation 00";
+    subject[23..].copy_from_slice(&hex_byte(key_ref));
+    let mut certificate_serial = [0u8; 17];
+    certificate_serial[..16].copy_from_slice(&card_uuid());
+    certificate_serial[16] = key_ref;
+
+    // DER INTEGER of the card serial number
+    let mut serial_integer = ArrayVec::<[u8; 8]>::new();
+    let mut serial_writer = DerWriter::new(&mut serial_integer);
+    serial_writer.integer(serial);
+
+    write_certificate(
+        w,
+        &keys::slot_private_key(keys::ATTESTATION_KEY_REF),
+        &certificate_serial,
+        ATTESTATION_NAME,
+        core::str::from_utf8(&subject).unwrap(),
+        &keys::public_key(&keys::slot_private_key(key_ref)),
+        |w| {
+            write_extension(w, &OID_YUBICO_FIRMWARE, false, version);
+            write_extension(w, &OID_YUBICO_SERIAL, false, &serial_integer);
+            write_extension(w, &OID_YUBICO_POLICY, false, policy);
+            write_extension(
+                w,
+                &OID_YUBICO_FORM_FACTOR,
+                false,
+                &[FORM_FACTOR_UNSPECIFIED],
+            );
+        },
+    );
+}
+
+/// Issuer of the content signer certificate, as referenced by signatures
+pub fn write_content_signer_name<A: Array<Item = u8>>(w: &mut DerWriter<A>) {
+    write_name(w, CONTENT_SIGNER_NAME);
+}
+
+/// Self-signed certificate of the content signing key.
+///
+/// It is the trust anchor of the signed data objects.
+pub fn write_content_signer_certificate<A: Array<Item = u8>>(
+    w: &mut DerWriter<A>,
+    pvkey: &cx_ecfp_private_key_t,
+) {
+    write_certificate(
+        w,
+        pvkey,
+        &content_signer_serial(),
+        CONTENT_SIGNER_NAME,
+        CONTENT_SIGNER_NAME,
+        &keys::public_key(pvkey),
+        |w| {
+            // digitalSignature
+            write_extension(w, &OID_KEY_USAGE, true, &[0x03, 0x02, 0x07, 0x80]);
+            let mut ext_key_usage = [0x30, 0x0A, 0x06, 0x08, 0, 0, 0, 0, 0, 0, 0, 0];
+            ext_key_usage[4..].copy_from_slice(&OID_PIV_CONTENT_SIGNING);
+            write_extension(w, &OID_EXT_KEY_USAGE, false, &ext_key_usage);
+        },
+    );
+}
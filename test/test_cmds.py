@@ -1,7 +1,27 @@
+import json
+import threading
+import time
+import urllib.request
+
+from Cryptodome.Cipher import DES, DES3
 from ledgerblue.commTCP import getDongle as getDongleTCP
 from ledgerblue.comm import getDongle
+from ledgerblue.commException import CommException
 
 SPECULOS = True
+SPECULOS_API = "http://127.0.0.1:5000"
+
+# Capacity of the store of objects unknown to the card, 1024 on the Nano S
+BLOB_STORE_SIZE = 4096
+
+DEFAULT_MGMT_KEY = bytes.fromhex("0102030405060708" * 3)
+
+# Generator of P-256, the public key of the private key 1
+P256_G = bytes.fromhex(
+    "04"
+    "6b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c296"
+    "4fe342e2fe1a7f9b8ee7eb4a7c0f9e162bce33576b315ececbb6406837bf51f5"
+)
 
 if SPECULOS:
     d = getDongleTCP(port=9999)  # Speculos
@@ -16,16 +36,142 @@ def exchange_and_expect(input_hex: str, expected_output_hex: str):
     assert r.hex() == expected_output_hex.lower()
 
 
+def exchange_and_expect_error(input_hex: str, expected_sw: int):
+    print(f"\n-> {input_hex}")
+    try:
+        d.exchange(bytes.fromhex(input_hex))
+    except CommException as e:
+        print(f"<- {e.sw:04x}")
+        assert e.sw == expected_sw
+    else:
+        assert False, "command succeeded"
+
+
+def press_right_button(presses: int):
+    for _ in range(presses):
+        # Let the device show the screen first
+        time.sleep(0.5)
+        request = urllib.request.Request(
+            f"{SPECULOS_API}/button/right",
+            data=json.dumps({"action": "press-and-release"}).encode(),
+            headers={"Content-Type": "application/json"},
+        )
+        urllib.request.urlopen(request)
+
+
+def exchange_approved(input_hex: str, presses: int = 1) -> bytes:
+    """Send a command approved on the device, by hand on a Nano"""
+    print(f"\n-> {input_hex}")
+    if SPECULOS:
+        threading.Thread(target=press_right_button, args=(presses,)).start()
+    r = d.exchange(bytes.fromhex(input_hex))
+    print(f"<- {r.hex()}")
+    return r
+
+
+def read_remaining(r: bytes) -> bytes:
+    """Complete a response holding a DER object split across responses"""
+    if r[1] < 0x80:
+        length = 2 + r[1]
+    else:
+        n = r[1] & 0x7F
+        length = 2 + n + int.from_bytes(r[2:2 + n], "big")
+    while len(r) < length:
+        r += d.exchange(bytes.fromhex("00c0000000"))
+    return r
+
+
+def exchange_long(input_hex: str) -> bytes:
+    """Send a command answering a DER object, which may take several responses"""
+    print(f"\n-> {input_hex}")
+    r = read_remaining(d.exchange(bytes.fromhex(input_hex)))
+    print(f"<- {r.hex()}")
+    return r
+
+
+def data_object(tag: str, value: bytes) -> bytes:
+    """Command data of a put data, writing `value` to the object `tag`"""
+    if len(value) < 0x80:
+        length = bytes([len(value)])
+    elif len(value) < 0x100:
+        length = bytes([0x81, len(value)])
+    else:
+        length = b"\x82" + len(value).to_bytes(2, "big")
+    return bytes.fromhex("5c03" + tag + "53") + length + value
+
+
+def put_data(data: bytes):
+    """Send a put data, chaining commands for data longer than 255 bytes"""
+    while len(data) > 255:
+        d.exchange(bytes.fromhex("10db3fffff") + data[:255])
+        data = data[255:]
+    d.exchange(bytes.fromhex("00db3fff") + bytes([len(data)]) + data)
+
+
+def select_piv():
+    """Select the PIV application, which resets its security status"""
+    d.exchange(bytes.fromhex("00a4040009a00000030800001000"))
+
+
+def mgmt_cipher(key: bytes):
+    # DES3 rejects keys repeating a single DES key, such as the default one
+    if key[:8] == key[8:16] == key[16:]:
+        return DES.new(key[:8], DES.MODE_ECB)
+    return DES3.new(key, DES3.MODE_ECB)
+
+
+def authenticate(key: bytes = DEFAULT_MGMT_KEY):
+    """Authenticate with the management key, answering a card challenge"""
+    r = d.exchange(bytes.fromhex("0087039b047c028100"))
+    assert r[:4].hex() == "7c0a8108"
+    response = mgmt_cipher(key).encrypt(r[4:])
+    exchange_and_expect("0087039b0c7c0a8208" + response.hex(), "")
+
+
 def test_select_card():
-    exchange_and_expect("00a4040009a00000030800001000", "61114f0600001000010079074f05a00000030800001000")
+    expected_url = b"https://github.com/aiooss-ledger/ledger-nano-piv".hex()
+    expected = (
+        "615a" + "4f06000010000100" + "79074f05a000000308" + "500a" + b"Ledger PIV".hex()
+        + "5f5030" + expected_url + "ac08" + "800103" + "800111" + "0600"
+    )
+    exchange_and_expect("00a4040009a00000030800001000", expected)
+    exchange_and_expect("00a404000ba000000308000010000100", expected)
+    exchange_and_expect("00a4040005a000000308", expected)
+
+
+def test_select_unknown_aid():
+    exchange_and_expect_error("00a4040009a00000030800002000", 0x6a82)
+    exchange_and_expect_error("00a4040007a0000006472f0001", 0x6a82)
+
+    # Selecting another application deselects the PIV application
+    exchange_and_expect_error("00cb3fff037e", 0x6985)
+    d.exchange(bytes.fromhex("00a4040009a00000030800001000"))
 
 
 def test_get_serial():
+    # Derived from the device serial number by default
+    serial = d.exchange(bytes.fromhex("00f8000000"))
+    assert len(serial) == 4
+
+    exchange_approved("00e1000006060431323334")
     exchange_and_expect("00f8000000", b"1234".hex())
+    exchange_approved("00e1000003060100")
+    exchange_and_expect("00f8000000", serial.hex())
 
 
 def test_get_version():
-    exchange_and_expect("00fd000000", "050400")
+    # P-384 keys, introduced in 4.0.0, are not supported
+    exchange_and_expect("00fd000000", "010004")
+
+    # Version reported to tools refusing older YubiKeys
+    exchange_approved("00e10000050703050700")
+    exchange_and_expect("00fd000000", "050700")
+    exchange_approved("00e10000020700")
+    exchange_and_expect("00fd000000", "010004")
+
+
+def test_get_discovery():
+    exchange_and_expect("00cb3fff037e", "7e124f0ba0000003080000100001005f2f024000")
 
 
 def test_get_key_history():
@@ -33,17 +179,17 @@ def test_get_key_history():
 
 
 def test_get_chuid():
-    expected_prefix = bytes([
-        0x53, 59, 0x30, 0x19
-        ]).hex()
     expected_fascn = "d4e739da739ced39ce739d836858210842108421cc339e23ff"
     expected_uuid = "34101968caafa6425f5d93f3499c5a436bc9"
-    expected_suffix = bytes([
-        0x35, 0x08, 0x32, 0x30, 0x35, 0x30, 0x30, 0x31, 0x30, 0x31, 0x3e, 0x00,
-        0xfe, 0x00
+    expected_expiry = bytes([
+        0x35, 0x08, 0x32, 0x30, 0x35, 0x30, 0x30, 0x31, 0x30, 0x31
         ]).hex()
 
-    exchange_and_expect("00cb3fff055c035FC102", expected_prefix + expected_fascn + expected_uuid + expected_suffix)
+    # Object is longer than a response APDU, only check its first chunk
+    r = d.exchange(bytes.fromhex("00cb3fff055c035FC102")).hex()
+    assert r.startswith("5382")
+    assert r[8:].startswith("3019" + expected_fascn + expected_uuid + expected_expiry + "3e82")
+
 
 def test_get_ccc():
     expected_prefix = bytes([
@@ -93,3 +239,266 @@ def test_get_retired_certificate():
         0x00, 0xfe, 0x00]).hex()
 
     exchange_and_expect("00cb3fff055c035fc10d", expected)
+
+
+def test_get_slot_certificates():
+    # PIV Authentication, Digital Signature, Key Management and Card
+    # Authentication certificates are generated when none is stored
+    for tag in ["5FC105", "5FC10A", "5FC10B", "5FC101"]:
+        r = d.exchange(bytes.fromhex("00cb3fff055c03" + tag)).hex()
+        assert r.startswith("5382")
+        assert r[8:].startswith("7082")
+
+
+def test_get_empty_retired_certificate():
+    exchange_and_expect_error("00cb3fff055c035fc10e", 0x6a82)
+
+
+def test_get_security_object():
+    r = d.exchange(bytes.fromhex("00cb3fff055c035FC106")).hex()
+    assert r.startswith("5382")
+    # Mapping of data groups 1 to 3 to the CHUID, CCC and Key History, the
+    # objects present on the card, followed by the signed LDS Security Object
+    assert r[8:].startswith("ba09013000" + "02db00" + "036060" + "bb82")
+
+
+def test_pin_protected_objects():
+    # Printed Information, Fingerprints, Facial Image and Iris Images
+    for tag in ["5FC109", "5FC103", "5FC108", "5FC121"]:
+        exchange_and_expect_error("00cb3fff055c03" + tag, 0x6982)
+
+
+def test_put_data_requires_management_key():
+    exchange_and_expect_error("00db3fff0d5c035FC1095306010448656c6c", 0x6982)
+
+
+def test_printed_information():
+    # Name, followed by the metadata written by Yubico tools
+    printed_information = "0104" + b"Hell".hex() + "8803890100"
+    authenticate()
+    exchange_and_expect("00db3fff125c035fc109530b" + printed_information, "")
+    # Expiration date longer than its 9 bytes
+    exchange_and_expect_error("00db3fff135c035fc109530c040a" + bytes(10).hex(), 0x6a80)
+
+    d.exchange(bytes.fromhex("0020008000"))
+    exchange_and_expect("00cb3fff055c035fc109", "530b" + printed_information)
+
+    exchange_and_expect("00db3fff075c035fc1095300", "")
+    exchange_and_expect_error("00cb3fff055c035fc109", 0x6a82)
+    select_piv()
+
+
+def test_put_chained():
+    # CBEFF wrapped fingerprints, too long for a single command
+    fingerprints = b"\xbc\x82\x01\x2c" + bytes(range(256)) + bytes(44)
+    data = data_object("5fc103", fingerprints)
+    authenticate()
+    # The end of the object is missing
+    exchange_and_expect_error("00db3fffff" + data[:255].hex(), 0x6700)
+
+    put_data(data)
+    d.exchange(bytes.fromhex("0020008000"))
+    assert exchange_long("00cb3fff055c035fc103") == data[5:]
+
+    put_data(data_object("5fc103", b""))
+    exchange_and_expect_error("00cb3fff055c035fc103", 0x6a82)
+    select_piv()
+
+
+def test_blob_store_full():
+    # Objects unknown to the card share a store, each one taking 5 more bytes
+    # for its tag and length. Two objects leave 86 bytes.
+    size = (BLOB_STORE_SIZE - 96) // 2
+    authenticate()
+    put_data(data_object("5fff10", bytes(size)))
+    put_data(data_object("5fff11", bytes(size)))
+    try:
+        put_data(data_object("5fff12", bytes(size)))
+    except CommException as e:
+        assert e.sw == 0x6a84
+    else:
+        assert False, "command succeeded"
+
+    # The remaining space, and the space of a replaced object, can be used
+    put_data(data_object("5fff12", bytes(81)))
+    value = bytes(i % 256 for i in range(size))
+    put_data(data_object("5fff10", value))
+    assert exchange_long("00cb3fff055c035fff10")[4:] == value
+    exchange_and_expect("00cb3fff055c035fff12", "5351" + bytes(81).hex())
+
+    for tag in ["5fff10", "5fff11", "5fff12"]:
+        put_data(data_object(tag, b""))
+        exchange_and_expect_error("00cb3fff055c03" + tag, 0x6a82)
+    select_piv()
+
+
+def test_get_metadata():
+    # Default 3DES management key
+    exchange_and_expect("00f7009b00", "010103" + "02020001" + "050101")
+    # ECC P-256 key derived from the seed
+    r = d.exchange(bytes.fromhex("00f7009a00")).hex()
+    assert r.startswith("010111" + "02020201" + "030101" + "0443864104")
+    # No PUK
+    exchange_and_expect_error("00f7008100", 0x6a88)
+
+
+def test_attest():
+    r = d.exchange(bytes.fromhex("00f99a0000")).hex()
+    assert r.startswith("3082")
+    # The attestation key cannot attest itself
+    exchange_and_expect_error("00f9f90000", 0x6a88)
+
+
+def test_generate_csr():
+    # CN=Test
+    subject = "300f310d300b0603550403" + "0c0454657374"
+    for key in ["9a", "9c", "9d", "9e"]:
+        public_key = d.exchange(bytes.fromhex("00f700" + key + "00"))[-65:]
+        csr = read_remaining(exchange_approved("00e011" + key + "11" + subject))
+        assert csr[0] == 0x30
+        assert bytes.fromhex(subject) in csr
+        assert public_key in csr
+
+    # Only primary slots
+    exchange_and_expect_error("00e0118211" + subject, 0x6a86)
+
+
+def test_import_requires_management_key():
+    exchange_and_expect_error("00fe119d2206200101010101010101010101010101010101010101010101010101010101010101", 0x6982)
+
+
+def test_import_key():
+    private_key = bytes(31) + b"\x01"
+    authenticate()
+    # Only P-256 keys are supported
+    exchange_and_expect_error("00fe14832206" + "20" + private_key.hex(), 0x6a81)
+    exchange_and_expect_error("00fe11832206" + "20" + bytes(32).hex(), 0x6a80)
+
+    exchange_and_expect("00fe11832206" + "20" + private_key.hex(), "")
+    # Imported origin, and public key of the imported key
+    exchange_and_expect("00f7008300", "010111" + "02020201" + "030102" + "04438641" + P256_G.hex())
+    # Only keys generated by the card can be attested
+    exchange_and_expect_error("00f9830000", 0x6985)
+
+    # ECDH with the generator gives the x-coordinate of the public key
+    d.exchange(bytes.fromhex("0020008000"))
+    r = d.exchange(bytes.fromhex("00871183477c4582008541") + P256_G)
+    assert r == bytes.fromhex("7c228220") + P256_G[1:33]
+
+    exchange_and_expect("00f6ff8300", "")
+    exchange_and_expect_error("00f7008300", 0x6a88)
+    select_piv()
+
+
+def test_pin_policy_always():
+    general_authenticate = "00871183477c4582008541" + P256_G.hex()
+    authenticate()
+    exchange_and_expect("00fe11832506" + "20" + (bytes(31) + b"\x01").hex() + "aa0103", "")
+    exchange_and_expect("00f7008300", "010111" + "02020301" + "030102" + "04438641" + P256_G.hex())
+
+    # The PIN must be verified right before each use, which is approved on
+    # the device
+    exchange_and_expect_error(general_authenticate, 0x6982)
+    d.exchange(bytes.fromhex("0020008000"))
+    r = exchange_approved(general_authenticate)
+    assert r == bytes.fromhex("7c228220") + P256_G[1:33]
+    exchange_and_expect_error(general_authenticate, 0x6982)
+
+    exchange_and_expect("00f6ff8300", "")
+    select_piv()
+
+
+def test_set_management_key_requires_management_key():
+    exchange_and_expect_error("00ffffff1b039b18" + "0102030405060708" * 3, 0x6982)
+
+
+def test_management_key_authentication():
+    # Challenge encrypted with another key
+    r = d.exchange(bytes.fromhex("0087039b047c028100"))
+    response = mgmt_cipher(bytes(range(24))).encrypt(r[4:])
+    exchange_and_expect_error("0087039b0c7c0a8208" + response.hex(), 0x6982)
+
+    # Mutual authentication: the host decrypts the card witness, then checks
+    # the card answer to its own challenge
+    r = d.exchange(bytes.fromhex("0087039b047c028000"))
+    assert r[:4].hex() == "7c0a8008"
+    witness = mgmt_cipher(DEFAULT_MGMT_KEY).decrypt(r[4:])
+    challenge = bytes.fromhex("0011223344556677")
+    r = d.exchange(bytes.fromhex("0087039b167c14") + b"\x80\x08" + witness + b"\x81\x08" + challenge)
+    assert r == bytes.fromhex("7c0a8208") + mgmt_cipher(DEFAULT_MGMT_KEY).encrypt(challenge)
+    select_piv()
+
+
+def test_set_management_key():
+    new_key = bytes.fromhex("0102030405060708" "1112131415161718" "2122232425262728")
+
+    authenticate()
+    exchange_and_expect("00fffffe1b039b18" + new_key.hex(), "")
+    exchange_and_expect("00f7009b00", "010103" + "02020002" + "050100")
+
+    # Admin operations now require touch, but invalid commands fail first
+    exchange_and_expect_error("00ffffff13039b10" + new_key[:16].hex(), 0x6a80)
+
+    # The previous key no longer authenticates
+    r = d.exchange(bytes.fromhex("0087039b047c028100"))
+    response = mgmt_cipher(DEFAULT_MGMT_KEY).encrypt(r[4:])
+    exchange_and_expect_error("0087039b0c7c0a8208" + response.hex(), 0x6982)
+
+    authenticate(new_key)
+    exchange_approved("00ffffff1b039b18" + DEFAULT_MGMT_KEY.hex())
+    exchange_and_expect("00f7009b00", "010103" + "02020001" + "050101")
+    select_piv()
+
+
+def test_set_pin_retries_not_supported():
+    # The PIN is the device PIN, whose retries cannot be changed
+    exchange_and_expect_error("00fa050300", 0x6a81)
+
+
+def test_move_key_requires_management_key():
+    exchange_and_expect_error("00f6839d00", 0x6982)
+
+
+def test_move_key():
+    public_key = d.exchange(bytes.fromhex("00f7009d00"))[-65:]
+    authenticate()
+
+    # Retired slots are populated in order, only 82 holds a key
+    exchange_and_expect_error("00f6859d00", 0x6985)
+    exchange_and_expect("00f6839d00", "")
+    exchange_and_expect_error("00f7009d00", 0x6a88)
+    assert d.exchange(bytes.fromhex("00f7008300"))[-65:] == public_key
+    exchange_and_expect("00cb3fff055c035FC10C", "5308c10102c20100fe00")
+    assert public_key in exchange_long("00cb3fff055c035fc10e")
+
+    # Only the last populated retired slot can be emptied
+    exchange_and_expect_error("00f6ff8200", 0x6985)
+    exchange_and_expect_error("00f6848300", 0x6985)
+
+    exchange_and_expect("00f69d8300", "")
+    assert d.exchange(bytes.fromhex("00f7009d00"))[-65:] == public_key
+    exchange_and_expect("00cb3fff055c035FC10C", "5308c10101c20100fe00")
+    exchange_and_expect_error("00cb3fff055c035fc10e", 0x6a82)
+    select_piv()
+
+
+def test_reset():
+    authenticate()
+    exchange_and_expect("00db3fff0d5c035FC1095306010448656c6c", "")
+    exchange_and_expect("00db3fff0a5c035fff005303010203", "")
+    exchange_and_expect("00cb3fff055c035fff00", "5303010203")
+    exchange_and_expect("00fe11832206" + "20" + (bytes(31) + b"\x01").hex(), "")
+    exchange_and_expect("00ffffff1b039b18" + "0102030405060708" "1112131415161718" "2122232425262728", "")
+    exchange_approved("00e1000006060431323334")
+
+    # Reset notice, then confirmation
+    exchange_approved("00fb000000", presses=2)
+
+    exchange_and_expect("00f7009b00", "010103" + "02020001" + "050101")
+    exchange_and_expect_error("00f7008300", 0x6a88)
+    exchange_and_expect("00cb3fff055c035FC10C", "5308c10101c20100fe00")
+    assert d.exchange(bytes.fromhex("00f8000000")) != b"1234"
+    exchange_and_expect_error("00cb3fff055c035fff00", 0x6a82)
+    d.exchange(bytes.fromhex("0020008000"))
+    exchange_and_expect_error("00cb3fff055c035FC109", 0x6a82)
+    select_piv()